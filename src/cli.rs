@@ -1,6 +1,11 @@
+use chrono::{Local, NaiveDate, NaiveDateTime};
 use clap::Parser;
+use glob::Pattern;
+use std::ffi::OsString;
+use std::time::SystemTime;
 
 use crate::config::TextEditor;
+use crate::validation::ConflictPolicy;
 
 #[derive(Parser)]
 #[clap(name="diffren", author, version, about, long_about = None)]
@@ -11,29 +16,237 @@ pub struct Args {
 
 #[derive(clap::Subcommand)]
 pub enum Subcommand {
-    Run {
-        /// Path(s) of the files to list.
-        /// Unix shell style patterns are supported.
-        #[clap(value_parser)]
-        paths: Vec<String>,
-    },
+    Run(RunArgs),
+
+    /// Copies the paths to their target names, instead of renaming them.
+    /// Folders are copied with their content.
+    Cp(RunArgs),
+
+    /// Creates links to the paths at their target names, instead of renaming them.
+    Ln(LnArgs),
 
     GetConfig,
 
-    /// Displays the current command use to start
+    /// Lists the batches of renamings applied, the most recent first.
+    History,
+
+    /// Reverts a batch of renamings from the history.
+    Undo {
+        /// Number of the batch in the history (1 is the most recent).
+        #[clap(value_parser, default_value_t = 1)]
+        number: usize,
+    },
+
+    /// Set the text editor to use.
     UseEditor {
+        #[clap(arg_enum, value_parser, required_unless_present = "detect")]
+        text_editor: Option<TextEditor>,
+
+        /// List the editors found on this machine instead.
+        #[clap(long, value_parser)]
+        detect: bool,
+    },
+
+    /// Set what to do when a target path already exists.
+    SetOnConflict {
         #[clap(arg_enum, value_parser)]
-        text_editor: TextEditor,
+        policy: ConflictPolicy,
     },
 
-    /// Set the custom editor's launch command, and use it.
+    /// Set the custom editor's launch command.
+    /// It is split into arguments like a shell would (quotes and escapes).
     /// The strings "{current}" and "{target}" will be replaced
     /// by the paths of the temporary files to edit. "{current}"
     /// is optionnal since it doesn't need to be modified,
     /// but it is needed if you want a diff view between the two files.
-    /// IE with VSCode: `diffren editor --set-custom="code --wait --diff {current} {target}"`
+    /// "{dir}" is replaced by the temporary directory containing them,
+    /// and "{cwd}" by the current working directory.
+    /// IE with VSCode: `diffren set-custom-editor "code --wait --diff {current} {target}"`
     SetCustomEditor {
         #[clap(value_parser)]
         command: String,
     },
 }
+
+#[derive(clap::Args)]
+pub struct RunArgs {
+    /// Path(s) of the files to list.
+    /// Unix shell style patterns are supported, and `-` reads the paths from stdin.
+    #[clap(value_parser)]
+    pub paths: Vec<OsString>,
+
+    #[clap(flatten)]
+    pub listing: ListingArgs,
+
+    /// All or nothing: if a renaming fails,
+    /// the renamings already done are reverted.
+    #[clap(long, value_parser)]
+    pub atomic: bool,
+
+    /// Only print what would be done, without renaming anything.
+    #[clap(long, value_parser)]
+    pub dry_run: bool,
+
+    /// Start each line with an ID (like `0042<TAB>path`),
+    /// so that target lines can be reordered, sorted or removed.
+    #[clap(long, value_parser)]
+    pub ids: bool,
+
+    /// Delete the paths whose target line is emptied or replaced by `!delete`
+    /// (or removed, with `--ids`). They're moved to the trash.
+    #[clap(long, value_parser)]
+    pub allow_delete: bool,
+
+    /// Delete the paths, and the targets replaced with the `overwrite` policy,
+    /// for good instead of moving them to the trash.
+    #[clap(long, value_parser)]
+    pub permanent: bool,
+
+    /// After the renamings, remove the folders they left empty,
+    /// up to the current directory.
+    #[clap(long, value_parser)]
+    pub prune_empty: bool,
+
+    /// What to do when a target path already exists,
+    /// instead of the policy set in the configuration.
+    #[clap(long, arg_enum, value_parser)]
+    pub on_conflict: Option<ConflictPolicy>,
+
+    /// Refuse to change the paths with changes not committed in git.
+    #[clap(long, value_parser)]
+    pub check_uncommitted: bool,
+
+    /// Validate the target names each time they're saved, while the editor is open.
+    /// Only with the editors that have their own window.
+    #[clap(long, value_parser)]
+    pub live: bool,
+}
+
+/// Which paths are listed
+#[derive(clap::Args)]
+pub struct ListingArgs {
+    /// Read the paths from stdin, one per line, like `-`.
+    #[clap(long, value_parser)]
+    pub stdin: bool,
+
+    /// The paths read from stdin are separated by NUL characters,
+    /// like the output of `find -print0`.
+    #[clap(short = '0', long, value_parser)]
+    pub null: bool,
+
+    /// List the content of the folders, recursively.
+    #[clap(short, long, value_parser)]
+    pub recursive: bool,
+
+    /// How deep to list the content of the folders.
+    #[clap(long, value_parser, requires = "recursive")]
+    pub max_depth: Option<usize>,
+
+    /// List the hidden paths too, like `.git` or `.env`.
+    #[clap(long, value_parser)]
+    pub hidden: bool,
+
+    /// Only list the files (and links).
+    #[clap(long, value_parser, conflicts_with = "dirs-only")]
+    pub files_only: bool,
+
+    /// Only list the folders.
+    #[clap(long, value_parser)]
+    pub dirs_only: bool,
+
+    /// Skip the paths ignored by `.gitignore` and `.ignore` files, like ripgrep does.
+    #[clap(long, value_parser, requires = "recursive")]
+    pub respect_gitignore: bool,
+
+    /// Skip the paths matching a pattern, and the content of the matching folders.
+    /// A pattern without `/` is matched against the names, like `--exclude '*.tmp'`.
+    #[clap(long, value_parser, value_name = "GLOB")]
+    pub exclude: Vec<Pattern>,
+
+    /// Only list the files (f), the folders (d) or the symbolic links (l).
+    #[clap(long = "type", arg_enum, value_parser, value_name = "TYPE")]
+    pub path_type: Option<PathType>,
+
+    /// Only list the paths modified after a date, like `2024-01-31` or `2024-01-31 18:30`.
+    #[clap(long, value_parser = parse_date, value_name = "DATE")]
+    pub newer: Option<SystemTime>,
+
+    /// Only list the paths modified before a date, like `2024-01-31` or `2024-01-31 18:30`.
+    #[clap(long, value_parser = parse_date, value_name = "DATE")]
+    pub older: Option<SystemTime>,
+
+    /// Only list the files of at least this size, like `500K`, `10M` or `1G`.
+    #[clap(long, value_parser = parse_size, value_name = "SIZE")]
+    pub min_size: Option<u64>,
+
+    /// Only list the files of at most this size, like `500K`, `10M` or `1G`.
+    #[clap(long, value_parser = parse_size, value_name = "SIZE")]
+    pub max_size: Option<u64>,
+}
+
+/// Type of path to list
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PathType {
+    #[clap(name = "f")]
+    File,
+    #[clap(name = "d")]
+    Folder,
+    #[clap(name = "l")]
+    Symlink,
+}
+
+/// Parses a date in the local time zone, with or without a time.
+fn parse_date(date: &str) -> Result<SystemTime, String> {
+    let date_time = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .ok_or("expected a date like `2024-01-31` or `2024-01-31 18:30`")?;
+
+    match date_time.and_local_timezone(Local).earliest() {
+        Some(date_time) => Ok(date_time.into()),
+        None => Err("this time doesn't exist in the local time zone".to_string()),
+    }
+}
+
+/// Parses a size in bytes, with an optional `K`, `M`, `G` or `T` suffix (powers of 1024).
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (number, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => size.split_at(index),
+        None => (size, ""),
+    };
+
+    let multiplier: u64 = match unit.to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err("expected a size like `500K`, `10M` or `1G`".to_string()),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| "expected a size like `500K`, `10M` or `1G`".to_string())
+}
+
+#[derive(clap::Args)]
+pub struct LnArgs {
+    #[clap(flatten)]
+    pub run: RunArgs,
+
+    /// Symbolic links point to absolute paths, instead of paths relative to the link.
+    #[clap(long, value_parser)]
+    pub absolute: bool,
+
+    /// Create hard links instead of symbolic links.
+    #[clap(long, value_parser, conflicts_with = "absolute")]
+    pub hard: bool,
+}
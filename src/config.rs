@@ -1,11 +1,36 @@
-use crate::filesystem::TempEditingFiles;
+use crate::filesystem::{BufferLayout, TempEditingFiles};
+use crate::validation::ConflictPolicy;
+use clap::ValueEnum;
 use colored::Colorize;
 use preferences::{AppInfo, Preferences};
 use serde::{Deserialize, Serialize};
-use std::{fmt::Display, path::PathBuf, process::Command};
+use std::{
+    ffi::{OsStr, OsString},
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 const KEY: &str = "user_prefs";
 
+/// Editors with a preset, from the best match to the worst when detecting them
+const DETECTABLE_EDITORS: [TextEditor; 11] = [
+    TextEditor::Vscode,
+    TextEditor::Vscodium,
+    TextEditor::Zed,
+    TextEditor::Meld,
+    TextEditor::Kdiff3,
+    TextEditor::SublimeMerge,
+    TextEditor::Jetbrains,
+    TextEditor::Kate,
+    TextEditor::Nvim,
+    TextEditor::Vimdiff,
+    TextEditor::Emacs,
+];
+
+/// Placeholders available in the custom editor launch command
+const PLACEHOLDERS: [&str; 4] = ["{current}", "{target}", "{dir}", "{cwd}"];
+
 const APP_INFO: AppInfo = AppInfo {
     qualifier: "",
     organization: "etn406",
@@ -16,6 +41,10 @@ const APP_INFO: AppInfo = AppInfo {
 pub struct DiffrenConfig {
     pub custom_editor: String,
     pub editor_to_use: Option<TextEditor>,
+
+    /// What to do when a target path already exists, unless `--on-conflict` is given
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
 }
 
 /// Available text editors
@@ -25,7 +54,19 @@ pub struct DiffrenConfig {
 pub enum TextEditor {
     Vscode,
     Vscodium,
+    Vimdiff,
+    Nvim,
+    Emacs,
+    Meld,
+    Kdiff3,
+    Kate,
+    SublimeMerge,
+    Zed,
+    /// The `idea` launcher of JetBrains IDEs
+    Jetbrains,
     Custom,
+    /// The terminal editor from `$VISUAL` or `$EDITOR`
+    Environment,
 }
 
 impl Display for TextEditor {
@@ -36,12 +77,72 @@ impl Display for TextEditor {
             match *self {
                 TextEditor::Vscode => "VSCode",
                 TextEditor::Vscodium => "VSCodium",
+                TextEditor::Vimdiff => "Vimdiff",
+                TextEditor::Nvim => "Neovim",
+                TextEditor::Emacs => "Emacs (ediff)",
+                TextEditor::Meld => "Meld",
+                TextEditor::Kdiff3 => "KDiff3",
+                TextEditor::Kate => "Kate",
+                TextEditor::SublimeMerge => "Sublime Merge",
+                TextEditor::Zed => "Zed",
+                TextEditor::Jetbrains => "JetBrains IDE",
                 TextEditor::Custom => "custom editor",
+                TextEditor::Environment => "$VISUAL/$EDITOR",
             }
         )
     }
 }
 
+/// How to start a diff-capable editor
+pub struct EditorPreset {
+    /// Name of the executable to find in `PATH`
+    pub binary: &'static str,
+
+    /// Flag making the command wait until the editor is closed
+    pub wait_flag: Option<&'static str>,
+
+    /// Arguments opening the diff view, with the `{current}` and `{target}` placeholders
+    pub diff_args: &'static [&'static str],
+}
+
+impl TextEditor {
+    /// Returns the launch command of the editor, except for the custom and environment ones.
+    pub fn preset(&self) -> Option<EditorPreset> {
+        let (binary, wait_flag, diff_args): (_, _, &'static [&'static str]) = match *self {
+            TextEditor::Vscode => ("code", Some("--wait"), &["--diff", "{current}", "{target}"]),
+            TextEditor::Vscodium => (
+                "codium",
+                Some("--wait"),
+                &["--diff", "{current}", "{target}"],
+            ),
+            TextEditor::Vimdiff => ("vimdiff", None, &["{current}", "{target}"]),
+            TextEditor::Nvim => ("nvim", None, &["-d", "{current}", "{target}"]),
+            TextEditor::Emacs => (
+                "emacs",
+                None,
+                &["--eval", "(ediff-files \"{current}\" \"{target}\")"],
+            ),
+            TextEditor::Meld => ("meld", None, &["{current}", "{target}"]),
+            TextEditor::Kdiff3 => ("kdiff3", None, &["{current}", "{target}", "-o", "{target}"]),
+            TextEditor::Kate => ("kate", Some("--block"), &["{current}", "{target}"]),
+            TextEditor::SublimeMerge => (
+                "smerge",
+                None,
+                &["mergetool", "{current}", "{target}", "-o", "{target}"],
+            ),
+            TextEditor::Zed => ("zed", Some("--wait"), &["--diff", "{current}", "{target}"]),
+            TextEditor::Jetbrains => ("idea", None, &["diff", "{current}", "{target}"]),
+            TextEditor::Custom | TextEditor::Environment => return None,
+        };
+
+        Some(EditorPreset {
+            binary,
+            wait_flag,
+            diff_args,
+        })
+    }
+}
+
 /// Reads and returns the current configuration.
 pub fn read_config() -> Result<DiffrenConfig, String> {
     match DiffrenConfig::load(&APP_INFO, KEY) {
@@ -52,9 +153,7 @@ pub fn read_config() -> Result<DiffrenConfig, String> {
 
 /// Save the custom editor launch command.
 pub fn set_custom_editor_command(command: &String) -> Result<(), String> {
-    if !command.contains("{target}") {
-        return Err("The command doesn't contain `{target}`.".to_string());
-    }
+    parse_custom_editor_command(command)?;
 
     let mut config = read_config().unwrap_or_default();
     config.custom_editor = command.to_owned();
@@ -86,6 +185,36 @@ pub fn set_editor_to_use(editor: &TextEditor) -> Result<(), String> {
     }
 }
 
+/// Save the policy applied when a target path already exists.
+pub fn set_conflict_policy(policy: &ConflictPolicy) -> Result<(), String> {
+    let mut config = read_config().unwrap_or_default();
+    config.on_conflict = *policy;
+
+    match save_config(&config) {
+        Ok(()) => {
+            println!(
+                "The policy for existing targets is now: {}",
+                get_conflict_policy_name(*policy).bright_black()
+            );
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns the policy applied when a target path already exists.
+pub fn get_conflict_policy() -> ConflictPolicy {
+    read_config().unwrap_or_default().on_conflict
+}
+
+/// Returns the name of a policy, as given on the command line.
+pub fn get_conflict_policy_name(policy: ConflictPolicy) -> String {
+    policy
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
 // Save the configuration.
 fn save_config(config: &DiffrenConfig) -> Result<(), String> {
     match config.save(&APP_INFO, KEY) {
@@ -94,47 +223,294 @@ fn save_config(config: &DiffrenConfig) -> Result<(), String> {
     }
 }
 
+/// Returns the editor set in the configuration,
+/// or the one from `$VISUAL`/`$EDITOR` if there isn't one.
+fn get_editor_to_use() -> Option<TextEditor> {
+    read_config()
+        .unwrap_or_default()
+        .editor_to_use
+        .or_else(|| get_environment_editor().map(|_| TextEditor::Environment))
+}
+
+/// Returns the editor command from `$VISUAL`, or else from `$EDITOR`.
+fn get_environment_editor() -> Option<String> {
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|command| !command.trim().is_empty())
+}
+
+/// Returns how the paths must be laid out in the temporary files for the editor to use.
+pub fn get_buffer_layout() -> BufferLayout {
+    match get_editor_to_use() {
+        Some(TextEditor::Environment) => BufferLayout::SingleFile,
+        _ => BufferLayout::TwoFiles,
+    }
+}
+
+/// Whether the editor to use runs in the terminal, where nothing else can be shown meanwhile.
+pub fn is_editor_in_terminal() -> bool {
+    matches!(
+        get_editor_to_use(),
+        Some(TextEditor::Vimdiff | TextEditor::Nvim | TextEditor::Environment)
+    )
+}
+
 /// Returns a `Command` ready to be spawned with the editor set in the configuration.
 pub fn get_editor_command(files: &TempEditingFiles) -> Command {
     let mut cmd;
 
-    match read_config().unwrap().editor_to_use.unwrap() {
-        TextEditor::Vscode => {
-            cmd = Command::new("code");
-            cmd.arg("--wait")
-                .arg("--diff")
-                .arg(&files.current)
-                .arg(&files.target);
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let values = [
+        files.current.as_os_str(),
+        files.target.as_os_str(),
+        files.dir_path().as_os_str(),
+        cwd.as_os_str(),
+    ];
+
+    match get_editor_to_use().unwrap() {
+        TextEditor::Custom => {
+            let config = read_config().unwrap();
+            let args = parse_custom_editor_command(&config.custom_editor)
+                .expect("The custom editor launch command is invalid.");
+
+            cmd = Command::new(replace_placeholders(&args[0], &values));
+            cmd.args(
+                args[1..]
+                    .iter()
+                    .map(|arg| replace_placeholders(arg, &values)),
+            );
         }
-        TextEditor::Vscodium => {
-            cmd = Command::new("codium");
-            cmd.arg("--wait")
-                .arg("--diff")
-                .arg(&files.current)
-                .arg(&files.target);
+        TextEditor::Environment => {
+            let command = get_environment_editor().unwrap();
+            let args = shlex::split(&command).unwrap_or_else(|| vec![command]);
+
+            cmd = Command::new(&args[0]);
+            cmd.args(&args[1..]).arg(&files.target);
+        }
+        editor => {
+            let preset = editor.preset().unwrap();
+
+            cmd = Command::new(preset.binary);
+            cmd.args(preset.wait_flag).args(
+                preset
+                    .diff_args
+                    .iter()
+                    .map(|arg| replace_placeholders(arg, &values)),
+            );
         }
-        TextEditor::Custom => todo!(),
     }
 
     cmd
 }
 
-/// Verify that an editor is defined
+/// Splits the custom editor launch command into arguments, with shell-style quoting,
+/// and checks that it contains `{target}` and only known placeholders.
+pub fn parse_custom_editor_command(command: &str) -> Result<Vec<String>, String> {
+    let args = match shlex::split(command) {
+        Some(args) if !args.is_empty() => args,
+        Some(_) => return Err("The command is empty.".to_string()),
+        None => return Err("The command's quotes or escapes are invalid.".to_string()),
+    };
+
+    if !args.iter().any(|arg| arg.contains("{target}")) {
+        return Err("The command doesn't contain `{target}`.".to_string());
+    }
+
+    for arg in &args {
+        let mut rest = arg.as_str();
+
+        while let Some(start) = rest.find('{') {
+            rest = &rest[start..];
+
+            let placeholder = rest
+                .find('}')
+                .map(|end| &rest[..=end])
+                .filter(|placeholder| {
+                    placeholder[1..placeholder.len() - 1]
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+                });
+
+            match placeholder {
+                Some(placeholder) if !PLACEHOLDERS.contains(&placeholder) => {
+                    return Err(format!(
+                        "Unknown placeholder `{placeholder}`, the available ones are: {}.",
+                        PLACEHOLDERS.join(", ")
+                    ))
+                }
+                _ => rest = &rest[1..],
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+/// Replaces the placeholders in an argument by their values (in the order of `PLACEHOLDERS`).
+fn replace_placeholders(arg: &str, values: &[&OsStr; 4]) -> OsString {
+    let mut result = OsString::new();
+    let mut rest = arg;
+
+    while !rest.is_empty() {
+        let found = PLACEHOLDERS
+            .iter()
+            .zip(values)
+            .find(|(placeholder, _)| rest.starts_with(*placeholder));
+
+        match found {
+            Some((placeholder, value)) => {
+                result.push(value);
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                let len = rest.chars().next().map_or(1, char::len_utf8);
+                result.push(&rest[..len]);
+                rest = &rest[len..];
+            }
+        }
+    }
+
+    result
+}
+
+/// Returns the full path of an executable, searching in `PATH` if it's only a name.
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    if name.contains(std::path::MAIN_SEPARATOR) {
+        return Some(PathBuf::from(name)).filter(|path| is_executable(path));
+    }
+
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .find(|path| is_executable(path))
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Returns the executable the editor is launched with.
+fn get_editor_binary(editor: TextEditor, config: &DiffrenConfig) -> Option<String> {
+    match editor {
+        TextEditor::Custom => shlex::split(&config.custom_editor)?.into_iter().next(),
+        TextEditor::Environment => shlex::split(&get_environment_editor()?)?.into_iter().next(),
+        editor => editor.preset().map(|preset| preset.binary.to_string()),
+    }
+}
+
+/// Returns the editors whose executable is found, from the best match to the worst,
+/// followed by the one from `$VISUAL`/`$EDITOR`.
+pub fn detect_editors() -> Vec<(TextEditor, PathBuf)> {
+    let config = DiffrenConfig::default();
+
+    DETECTABLE_EDITORS
+        .iter()
+        .chain([TextEditor::Environment].iter())
+        .filter_map(|editor| {
+            let binary = get_editor_binary(*editor, &config)?;
+            find_executable(&binary).map(|path| (*editor, path))
+        })
+        .collect()
+}
+
+/// Lists the editors found on this machine.
+pub fn print_detected_editors() -> Result<(), String> {
+    let editors = detect_editors();
+
+    if editors.is_empty() {
+        return Err("No known editor was found in PATH.".to_string());
+    }
+
+    println!("{}", "Editors found:".bold());
+
+    for (index, (editor, path)) in editors.iter().enumerate() {
+        println!(
+            "• {} {} {}{}",
+            get_editor_value_name(*editor),
+            editor.to_string().trim_end().bright_black(),
+            path.to_str().unwrap_or("?").italic(),
+            if index == 0 { " (best match)" } else { "" }
+        );
+    }
+
+    println!("\nUse one of them with `diffren use-editor <TEXT_EDITOR>`.");
+
+    Ok(())
+}
+
+/// Returns the name of the editor on the command line.
+fn get_editor_value_name(editor: TextEditor) -> String {
+    clap::ValueEnum::to_possible_value(&editor)
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Verify that an editor is defined, and that its executable can be found.
+/// Without configuration nor `$VISUAL`/`$EDITOR`, the best editor found is saved.
 pub fn is_there_an_editor_to_use() -> Result<(), String> {
     let config = read_config().unwrap_or_default();
 
-    match config.editor_to_use {
+    if get_editor_to_use().is_none() {
+        if let Some((editor, _)) = detect_editors().first() {
+            println!(
+                "No editor is set, {} has been detected.",
+                editor.to_string().trim_end().bold()
+            );
+            set_editor_to_use(editor)?;
+        }
+    }
+
+    is_the_editor_defined(&config)?;
+
+    let editor = get_editor_to_use().unwrap();
+
+    match get_editor_binary(editor, &config) {
+        Some(binary) if find_executable(&binary).is_none() => Err(format!(
+            "The executable `{binary}` of {} couldn't be found in PATH. Install it, or choose another editor with `diffren use-editor`.",
+            editor.to_string().trim_end()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Verify that an editor is defined
+fn is_the_editor_defined(config: &DiffrenConfig) -> Result<(), String> {
+    match get_editor_to_use() {
         Some(TextEditor::Custom) => {
             if config.custom_editor == "" {
                 Err("No custom editor launch command defined".to_string())
             } else {
-                Ok(())
+                match parse_custom_editor_command(&config.custom_editor) {
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(format!("Invalid custom editor launch command: {}", err)),
+                }
             }
         }
+        Some(TextEditor::Environment) => match get_environment_editor() {
+            Some(command) if shlex::split(&command).is_some_and(|args| !args.is_empty()) => Ok(()),
+            Some(command) => Err(format!(
+                "Invalid editor command in $VISUAL/$EDITOR: {command}"
+            )),
+            None => Err("Neither $VISUAL nor $EDITOR is defined.".to_string()),
+        },
         Some(_) => Ok(()),
-        None => Err("You have to set which text editor to use!"
-            .bold()
-            .to_string()),
+        None => Err(
+            "You have to set which text editor to use, or define $VISUAL or $EDITOR!"
+                .bold()
+                .to_string(),
+        ),
     }
 }
 
@@ -146,6 +522,23 @@ pub fn get_path_to_config() -> String {
         .to_string()
 }
 
+/// Returns the path to the renamings history, next to the configuration.
+pub fn get_path_to_history() -> Result<PathBuf, String> {
+    get_path_in_config_dir("history.json")
+}
+
+/// Returns the path to the journal of the batch being applied, next to the configuration.
+pub fn get_path_to_journal() -> Result<PathBuf, String> {
+    get_path_in_config_dir("journal.jsonl")
+}
+
+fn get_path_in_config_dir(file_name: &str) -> Result<PathBuf, String> {
+    match preferences::prefs_base_dir(&APP_INFO) {
+        Some(dir) => Ok(dir.join(file_name)),
+        None => Err("Couldn't find the configuration directory.".to_string()),
+    }
+}
+
 pub fn print_config() -> Result<(), String> {
     match read_config() {
         Ok(config) => {
@@ -164,6 +557,15 @@ pub fn print_config() -> Result<(), String> {
 
             if let Some(editor) = config.editor_to_use {
                 println!("• Editor to use: {}", editor.to_string().bright_black());
+            } else if let Some(command) = get_environment_editor() {
+                println!(
+                    "• Editor to use: {} ({})",
+                    TextEditor::Environment
+                        .to_string()
+                        .trim_end()
+                        .bright_black(),
+                    command.bright_black()
+                );
             } else {
                 println!("• Editor to use: {}", "undefined".red());
                 println!(
@@ -172,6 +574,11 @@ pub fn print_config() -> Result<(), String> {
                 );
             }
 
+            println!(
+                "• When a target exists: {}",
+                get_conflict_policy_name(config.on_conflict).bright_black()
+            );
+
             Ok(())
         }
         Err(err) => Err(err),
@@ -1,51 +1,76 @@
-use std::{cmp::max, io, path::PathBuf};
+use std::collections::HashMap;
+use std::{cmp::max, path::PathBuf};
 
-use colored::{ColoredString, Colorize};
+use colored::{Color, ColoredString, Colorize};
 
-use crate::validation::{Renaming, Validation, ValidationError};
+use crate::escaping::escape_path;
+use crate::filesystem::{Outcome, RenamingsResults};
+use crate::planning::{Operation, PlannedStep, Step};
+use crate::validation::{Change, Renaming, Validation, ValidationError};
 
 type Table = Vec<TableRow>;
 type TableRow = [ColoredString; 3];
 
 /// Displays a pretty list with all the renamings validations and errors
-pub fn display_validations(validations: &Vec<Validation>) {
+pub fn display_validations(validations: &Vec<Validation>, operation: Operation) {
+    let valid = match operation {
+        Operation::Move => "can be renamed",
+        Operation::Copy => "can be copied",
+        Operation::Symlink { .. } | Operation::HardLink => "can be linked",
+    };
+
     let table = validations
         .iter()
         .filter_map(|validation| -> Option<TableRow> {
-            let fmt = |path: &PathBuf| path.to_str().unwrap_or("?").bright_black();
+            let fmt = |path: &PathBuf| escape_path(path).bright_black();
 
             match &validation {
-                Ok(renaming) => Some([
+                Ok(Change::Rename(renaming)) => Some([
                     fmt(&renaming.0).strikethrough(),
                     fmt(&renaming.1).green(),
-                    "can be renamed".green(),
+                    valid.green(),
                 ]),
-                Err(ValidationError::FileDoesntExist(renaming)) => Some([
-                    fmt(&renaming.0).red(),
-                    fmt(&renaming.1),
-                    "this file doesn't exist".italic().red(),
+                Ok(Change::Overwrite(renaming)) => Some([
+                    fmt(&renaming.0).strikethrough(),
+                    fmt(&renaming.1).yellow(),
+                    "will replace the existing output".yellow(),
                 ]),
-                Err(ValidationError::InvalidCurrentPath(renaming)) => Some([
-                    fmt(&renaming.0).red(),
-                    fmt(&renaming.1),
-                    "invalid current path".italic().red(),
+                Ok(Change::Backup(renaming, backup)) => Some([
+                    fmt(&renaming.0).strikethrough(),
+                    fmt(&renaming.1).green(),
+                    format!("existing output backed up to {}", escape_path(backup)).yellow(),
                 ]),
-                Err(ValidationError::TargetAlreadyExists(renaming)) => Some([
-                    fmt(&renaming.0),
-                    fmt(&renaming.1).red(),
-                    "output already exists".italic().red(),
+                Ok(Change::Numbered(renaming)) => Some([
+                    fmt(&renaming.0).strikethrough(),
+                    fmt(&renaming.1).green(),
+                    "output already exists, numbered".yellow(),
                 ]),
-                Err(ValidationError::InvalidTargetPath(renaming)) => Some([
-                    fmt(&renaming.0),
-                    fmt(&renaming.1).red(),
-                    "invalid target path".italic().red(),
+                Ok(Change::Delete(path)) => Some([
+                    fmt(path).red().strikethrough(),
+                    "".normal(),
+                    "will be deleted".yellow(),
+                ]),
+                Err(
+                    error @ (ValidationError::FileDoesntExist(renaming)
+                    | ValidationError::InvalidCurrentPath(renaming)
+                    | ValidationError::HasUncommittedChanges(renaming)
+                    | ValidationError::FolderCantBeHardLinked(renaming)),
+                ) => Some([
+                    fmt(&renaming.0).red(),
+                    fmt(&renaming.1),
+                    get_error_message(error).italic().red(),
                 ]),
-                Err(ValidationError::SeveralTargetsAreTheSame(renaming, count)) => Some([
+                Err(
+                    error @ (ValidationError::TargetAlreadyExists(renaming)
+                    | ValidationError::InvalidTargetPath(renaming)
+                    | ValidationError::TargetInsideItself(renaming)
+                    | ValidationError::TargetInsideLinkedFolder(renaming)
+                    | ValidationError::TargetIsIgnored(renaming)
+                    | ValidationError::SeveralTargetsAreTheSame(renaming, _)),
+                ) => Some([
                     fmt(&renaming.0),
                     fmt(&renaming.1).red(),
-                    format!("several ({count}) outputs are the same")
-                        .italic()
-                        .red(),
+                    get_error_message(error).italic().red(),
                 ]),
 
                 // Unchanged lines aren't displayed
@@ -57,20 +82,193 @@ pub fn display_validations(validations: &Vec<Validation>) {
     display_table(table, "→");
 }
 
-/// Display a table with all the results
-pub fn display_results(results: &Vec<(Renaming, io::Result<()>)>) {
-    let to_str = |p: &PathBuf| p.to_str().unwrap_or("?").normal();
+/// Displays the target paths edited since the previous round,
+/// as `current → previous target → new target`.
+pub fn display_changes_since_last_edit(previous: &[Renaming], renamings: &[Renaming]) {
+    let previous: HashMap<&PathBuf, &PathBuf> = previous
+        .iter()
+        .map(|(current, target)| (current, target))
+        .collect();
+
+    let table: Table = renamings
+        .iter()
+        .filter_map(|(current, target)| match previous.get(current) {
+            Some(previous_target) if *previous_target != target => Some([
+                escape_path(current).bright_black(),
+                escape_path(previous_target).strikethrough(),
+                escape_path(target).green(),
+            ]),
+            _ => None,
+        })
+        .collect();
+
+    if table.is_empty() {
+        println!("\n{}", "Nothing changed since the last edit.".yellow());
+    } else {
+        println!("\n{}", "Changed since the last edit:".bold());
+        display_table(table, "→");
+    }
+}
+
+/// Describes a validation error, in the table or next to its line in the editor.
+pub fn get_error_message(error: &ValidationError) -> String {
+    match error {
+        ValidationError::FileDoesntExist(_) => "this file doesn't exist".to_string(),
+        ValidationError::InvalidCurrentPath(_) => "invalid current path".to_string(),
+        ValidationError::HasUncommittedChanges(_) => "uncommitted changes".to_string(),
+        ValidationError::TargetAlreadyExists(_) => "output already exists".to_string(),
+        ValidationError::InvalidTargetPath(_) => "invalid target path".to_string(),
+        ValidationError::TargetInsideItself(_) => "output is inside the input".to_string(),
+        ValidationError::TargetIsIgnored(_) => "output is ignored by git".to_string(),
+        ValidationError::TargetInsideLinkedFolder(_) => {
+            "output is inside a linked folder".to_string()
+        }
+        ValidationError::FolderCantBeHardLinked(_) => "a folder can't be hard linked".to_string(),
+        ValidationError::SeveralTargetsAreTheSame(_, count) => {
+            format!("several ({count}) outputs are the same")
+        }
+        ValidationError::Unchanged(_) => "unchanged".to_string(),
+    }
+}
 
+/// Display a table with all the results, and the rollback if there was one
+pub fn display_results(results: &RenamingsResults) {
     display_table(
         results
+            .results
             .iter()
-            .map(|((current, target), result)| match result {
-                Ok(()) => [to_str(current), to_str(target), "✓ Renamed".green()],
-                Err(e) => [to_str(current), to_str(target), format!("✗ {}", e).red()],
+            .map(|(step, result)| {
+                let done = match step {
+                    Step::Rename(_) | Step::GitRename(_) => "✓ Renamed",
+                    Step::Trash(_) => "✓ Moved to the trash",
+                    Step::Restore(_) => "✓ Restored",
+                    Step::Delete(_) => "✓ Deleted",
+                    Step::Copy(_) => "✓ Copied",
+                    Step::Symlink(_) | Step::HardLink(_) => "✓ Linked",
+                };
+
+                get_result_row(step, result, done, Color::Green)
             })
+            .chain(results.pruned.iter().map(|(folder, result)| {
+                let folder = escape_path(folder).normal();
+
+                match result {
+                    Ok(()) => [folder, "".normal(), "✓ Removed empty folder".cyan()],
+                    Err(e) => [folder, "".normal(), format!("✗ {}", e).red()],
+                }
+            }))
             .collect(),
         "→",
     );
+
+    if let Some(rollback) = &results.rollback {
+        println!(
+            "{}",
+            "A renaming failed, the renamings already done are reverted:"
+                .bold()
+                .red()
+        );
+
+        display_table(
+            rollback
+                .iter()
+                .map(|(step, result)| get_result_row(step, result, "✓ Reverted", Color::Yellow))
+                .collect(),
+            "→",
+        );
+
+        if rollback.iter().any(|(_, result)| result.is_err()) {
+            println!(
+                "{}",
+                "Some renamings couldn't be reverted, these paths must be fixed by hand."
+                    .bold()
+                    .red()
+            );
+        }
+    }
+}
+
+/// Returns the row of a step with its result, `done` being displayed on success
+fn get_result_row(
+    step: &Step,
+    result: &std::io::Result<Outcome>,
+    done: &str,
+    color: Color,
+) -> TableRow {
+    let to_str = |p: &PathBuf| escape_path(p).normal();
+
+    let (current, target) = match step {
+        Step::Delete(path) => (to_str(path), "".normal()),
+        Step::Rename((current, target))
+        | Step::GitRename((current, target))
+        | Step::Trash((current, target))
+        | Step::Restore((current, target))
+        | Step::Copy((current, target))
+        | Step::Symlink((current, target))
+        | Step::HardLink((current, target)) => (to_str(current), to_str(target)),
+    };
+
+    match result {
+        Ok(Outcome::Done) => [current, target, done.color(color)],
+        Ok(Outcome::CopiedAcrossDevices) => [
+            current,
+            target,
+            format!("{done} (copied to another file system)").color(color),
+        ],
+        Err(e) => [current, target, format!("✗ {}", e).red()],
+    }
+}
+
+/// Display a table with the steps of a plan, without applying it
+pub fn display_plan(steps: &[PlannedStep]) {
+    let to_str = |p: &PathBuf| escape_path(p).normal();
+
+    display_table(
+        steps
+            .iter()
+            .map(|step| match step {
+                PlannedStep::CreateFolder(folder) => {
+                    ["".normal(), to_str(folder), "new folder".cyan()]
+                }
+                PlannedStep::Rename((current, target)) => {
+                    [to_str(current), to_str(target), "rename".green()]
+                }
+                PlannedStep::ToTemporary((current, target)) => [
+                    to_str(current),
+                    to_str(target).italic(),
+                    "temporary name".yellow(),
+                ],
+                PlannedStep::FromTemporary((current, target)) => [
+                    to_str(current).italic(),
+                    to_str(target),
+                    "rename from temporary name".green(),
+                ],
+                PlannedStep::Trash((current, trashed)) => [
+                    to_str(current),
+                    to_str(trashed),
+                    "move to the trash".yellow(),
+                ],
+                PlannedStep::Restore((trashed, target)) => [
+                    to_str(trashed),
+                    to_str(target),
+                    "restore from the trash".green(),
+                ],
+                PlannedStep::Delete(path) => [to_str(path), "".normal(), "delete".red()],
+                PlannedStep::Copy((current, target)) => {
+                    [to_str(current), to_str(target), "copy".green()]
+                }
+                PlannedStep::Symlink((content, link)) => {
+                    [to_str(content), to_str(link), "symbolic link".green()]
+                }
+                PlannedStep::HardLink((current, link)) => {
+                    [to_str(current), to_str(link), "hard link".green()]
+                }
+            })
+            .collect(),
+        "→",
+    );
+
+    println!("{}", "Dry run: nothing has been renamed.".bold());
 }
 
 /// Displays a three columns table
@@ -0,0 +1,277 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::validation::is_deletion_line;
+
+/// Writes a path as text on a single line, so that `unescape_path` gives back the exact path.
+/// The control characters are escaped like in C (`\n`, `\t`, `\r` or `\xNN`),
+/// and so are the bytes that aren't valid UTF-8 (`\xNN`) and the backslashes (`\\`).
+/// A name with leading or trailing whitespace, starting with `"` or `#`,
+/// or that would be read as a deletion line, is quoted.
+pub fn escape_path(path: &Path) -> String {
+    let mut escaped = String::new();
+
+    for chunk in path.as_os_str().as_encoded_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\t' => escaped.push_str("\\t"),
+                '\r' => escaped.push_str("\\r"),
+                c if c.is_control() => {
+                    let mut buffer = [0; 4];
+
+                    for byte in c.encode_utf8(&mut buffer).as_bytes() {
+                        escaped.push_str(&format!("\\x{byte:02X}"));
+                    }
+                }
+                c => escaped.push(c),
+            }
+        }
+
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("\\x{byte:02X}"));
+        }
+    }
+
+    let needs_quotes = escaped.starts_with(char::is_whitespace)
+        || escaped.ends_with(char::is_whitespace)
+        || escaped.starts_with(['"', '#'])
+        || is_deletion_line(&escaped);
+
+    if needs_quotes {
+        format!("\"{}\"", escaped.replace('"', "\\\""))
+    } else {
+        escaped
+    }
+}
+
+/// Reads a path written by `escape_path`. The whitespace around a quoted path is ignored,
+/// but it's part of a path without quotes.
+pub fn unescape_path(text: &str) -> Result<PathBuf, String> {
+    let quoted = text.trim().starts_with('"');
+
+    let mut bytes = vec![];
+    let mut chars = if quoted {
+        text.trim()[1..].chars()
+    } else {
+        text.chars()
+    };
+
+    let mut is_closed = false;
+
+    while let Some(c) = chars.next() {
+        if is_closed {
+            return Err(format!(
+                "The text after the closing quote in `{text}` isn't valid."
+            ));
+        }
+
+        if quoted && c == '"' {
+            is_closed = true;
+            continue;
+        }
+
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('\\') => bytes.push(b'\\'),
+            Some('"') => bytes.push(b'"'),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('r') => bytes.push(b'\r'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 => bytes.push(byte),
+                    _ => return Err(format!("The escape `\\x{hex}` in `{text}` isn't valid.")),
+                }
+            }
+            Some(c) => return Err(format!("The escape `\\{c}` in `{text}` isn't valid.")),
+            None => {
+                return Err(format!(
+                    "The backslash at the end of `{text}` isn't escaped."
+                ))
+            }
+        }
+    }
+
+    if quoted && !is_closed {
+        return Err(format!("The quote in `{text}` isn't closed."));
+    }
+
+    Ok(path_from_bytes(bytes))
+}
+
+/// Makes a path from raw bytes, like the ones read from stdin or written by git.
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+/// Makes a path from raw bytes, the ones that aren't valid UTF-8 are replaced.
+#[cfg(not(unix))]
+pub fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Paths in JSON, as escaped strings
+pub mod serde_path {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::path::{Path, PathBuf};
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::escape_path(path))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        super::unescape_path(&text).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Renamings in JSON, as pairs of escaped strings
+pub mod serde_renaming {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{escape_path, unescape_path};
+    use crate::validation::Renaming;
+
+    pub fn serialize<S: Serializer>(
+        (current, target): &Renaming,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        (escape_path(current), escape_path(target)).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Renaming, D::Error> {
+        let (current, target) = <(String, String)>::deserialize(deserializer)?;
+
+        match (unescape_path(&current), unescape_path(&target)) {
+            (Ok(current), Ok(target)) => Ok((current, target)),
+            (Err(err), _) | (_, Err(err)) => Err(serde::de::Error::custom(err)),
+        }
+    }
+}
+
+/// Lists of renamings in JSON
+pub mod serde_renamings {
+    use serde::ser::SerializeSeq;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::escape_path;
+    use crate::validation::Renaming;
+
+    pub fn serialize<S: Serializer>(
+        renamings: &[Renaming],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(renamings.len()))?;
+
+        for (current, target) in renamings {
+            seq.serialize_element(&(escape_path(current), escape_path(target)))?;
+        }
+
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Renaming>, D::Error> {
+        #[derive(Deserialize)]
+        struct Escaped(#[serde(with = "super::serde_renaming")] Renaming);
+
+        let renamings = Vec::<Escaped>::deserialize(deserializer)?;
+        Ok(renamings
+            .into_iter()
+            .map(|Escaped(renaming)| renaming)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that a name is written as expected, and read back to the same name.
+    fn assert_round_trip(name: &[u8], escaped: &str) {
+        let path = path_from_bytes(name.to_vec());
+
+        assert_eq!(escape_path(&path), escaped);
+        assert_eq!(unescape_path(escaped), Ok(path));
+    }
+
+    #[test]
+    fn keeps_plain_names() {
+        assert_round_trip(b"plain name.txt", "plain name.txt");
+        assert_round_trip("café/été.txt".as_bytes(), "café/été.txt");
+        assert_round_trip(b"a \"quote\" # inside", "a \"quote\" # inside");
+    }
+
+    #[test]
+    fn escapes_control_characters() {
+        assert_round_trip(b"two\nlines", "two\\nlines");
+        assert_round_trip(b"a\ttab", "a\\ttab");
+        assert_round_trip(b"carriage\rreturn", "carriage\\rreturn");
+        assert_round_trip(b"bell\x07", "bell\\x07");
+        assert_round_trip("next\u{85}line".as_bytes(), "next\\xC2\\x85line");
+    }
+
+    #[test]
+    fn escapes_backslashes() {
+        assert_round_trip(b"back\\slash", "back\\\\slash");
+        assert_round_trip(b"\\x41", "\\\\x41");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn escapes_invalid_utf8() {
+        assert_round_trip(b"caf\xE9.txt", "caf\\xE9.txt");
+        assert_round_trip(b"\xFF\xFE", "\\xFF\\xFE");
+    }
+
+    #[test]
+    fn quotes_names_with_edge_whitespace() {
+        assert_round_trip(b"  intro.txt", "\"  intro.txt\"");
+        assert_round_trip(b"outro.txt ", "\"outro.txt \"");
+        // An escaped tab isn't whitespace anymore.
+        assert_round_trip(b"\ttab first", "\\ttab first");
+    }
+
+    #[test]
+    fn quotes_names_starting_with_a_quote_or_a_comment() {
+        assert_round_trip(b"\"quoted\"", "\"\\\"quoted\\\"\"");
+        assert_round_trip(b"# not a comment", "\"# not a comment\"");
+        assert_round_trip(b"#hash", "\"#hash\"");
+    }
+
+    #[test]
+    fn quotes_names_looking_like_a_deletion() {
+        assert_round_trip(b"!delete", "\"!delete\"");
+        assert_round_trip(b"!delete me", "\"!delete me\"");
+        assert_round_trip(b"!deleted scenes.mkv", "!deleted scenes.mkv");
+    }
+
+    #[test]
+    fn ignores_the_whitespace_around_quotes() {
+        assert_eq!(unescape_path("  \" a \"  "), Ok(PathBuf::from(" a ")));
+        assert_eq!(unescape_path(" a "), Ok(PathBuf::from(" a ")));
+    }
+
+    #[test]
+    fn refuses_invalid_escapes_and_quotes() {
+        assert!(unescape_path("a\\q").is_err());
+        assert!(unescape_path("a\\x4").is_err());
+        assert!(unescape_path("a\\xZZ").is_err());
+        assert!(unescape_path("end\\").is_err());
+        assert!(unescape_path("\"unclosed").is_err());
+        assert!(unescape_path("\"closed\" after").is_err());
+    }
+}
@@ -1,43 +1,248 @@
 use colored::Colorize;
-use glob::glob;
+use glob::{MatchOptions, Pattern, PatternError};
+use ignore::WalkBuilder;
+use std::cmp::max;
+use std::ffi::OsString;
 use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::BufRead;
+use std::io::Read;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use tempfile::TempDir;
 
-use crate::validation::Renaming;
+use crate::cli::{ListingArgs, PathType};
+use crate::config;
+use crate::escaping::{escape_path, path_from_bytes};
+use crate::git;
+use crate::journal::{Entry, Journal};
+use crate::planning::{get_partial_copy_path, Step};
+use crate::trash;
+use crate::validation::{self, ConflictPolicy, Renaming, DELETE_PREFIX};
+
+/// Comment lines start with this prefix, they're skipped when the files are read
+const COMMENT_PREFIX: &str = "#";
+
+/// Comment lines describing the error of the next line, replaced at each retry
+const ERROR_PREFIX: &str = "#!";
+
+/// How the paths are laid out in the temporary files
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BufferLayout {
+    /// The current names and the target names are in two files, compared in a diff view
+    TwoFiles,
+
+    /// Only the target names file is edited, each target name is preceded
+    /// by its current name in a comment line
+    SingleFile,
+}
+
+/// How the paths are written in the temporary files
+#[derive(Clone, Copy)]
+pub struct BufferFormat {
+    pub layout: BufferLayout,
+
+    /// Each line starts with a stable ID, like `0042<TAB>path`,
+    /// so that the target lines can be reordered or removed
+    pub line_ids: bool,
+
+    /// An empty target line, or a `DELETE_PREFIX` one,
+    /// deletes its path (and so does a removed line with IDs)
+    pub allow_delete: bool,
+}
 
 /// Directory and temporary files
 pub struct TempEditingFiles {
     dir: TempDir,
 
-    /// Temporary file containing current files paths
+    /// Format of the paths in the files
+    pub format: BufferFormat,
+
+    /// Content written in the current file, trusted instead of the file
+    current_content: String,
+
+    /// Temporary file containing current files paths, read-only
     pub current: PathBuf,
 
     /// Temporary file containing the current files paths, meant to be edited
     pub target: PathBuf,
 }
 
+impl TempEditingFiles {
+    /// Path to the directory containing the temporary files
+    pub fn dir_path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Lines of the current file, as they were written
+    pub fn current_lines(&self) -> Vec<String> {
+        self.current_content.lines().map(str::to_string).collect()
+    }
+}
+
 /// Create the pair of temporary files necessary for editing.
-pub fn init_temporary_files(content: String) -> TempEditingFiles {
+/// The target file starts with a header describing how it's read.
+pub fn init_temporary_files(
+    paths: &[PathBuf],
+    format: BufferFormat,
+    on_conflict: ConflictPolicy,
+) -> TempEditingFiles {
     let dir = TempDir::new().expect("Couldn't create temp dir");
+    let header = get_header(paths.len(), format, on_conflict);
+    let paths: Vec<String> = paths.iter().map(|path| escape_path(path)).collect();
+
+    let lines: Vec<String> = if format.line_ids {
+        let width = max(4, paths.len().to_string().len());
+
+        paths
+            .iter()
+            .enumerate()
+            .map(|(index, path)| format!("{:0width$}\t{path}", index + 1))
+            .collect()
+    } else {
+        paths.to_vec()
+    };
+
+    // Each line ends with a newline, so that an emptied last line is still read.
+    let content: String = lines.iter().map(|line| format!("{line}\n")).collect();
+
+    let target_content = match format.layout {
+        BufferLayout::TwoFiles => header + &content,
+        BufferLayout::SingleFile => paths
+            .iter()
+            .zip(&lines)
+            .fold(header, |content, (path, line)| {
+                content + &format!("{COMMENT_PREFIX} {path}\n{line}\n")
+            }),
+    };
 
     let current = create_temporary_file(&dir, "current names", &content)
+        .and_then(|current| set_read_only(&current).map(|()| current))
         .expect("Couldn't create temp file A");
 
-    let target =
-        create_temporary_file(&dir, "target names", &content).expect("Couldn't create temp file B");
+    let target = create_temporary_file(&dir, "target names", &target_content)
+        .expect("Couldn't create temp file B");
 
     TempEditingFiles {
         dir,
+        format,
+        current_content: content,
         current,
         target,
     }
 }
 
+/// Writes the current file again if it was changed or replaced in the editor,
+/// and returns whether it was.
+pub fn restore_current_file(temp: &TempEditingFiles) -> io::Result<bool> {
+    let is_changed =
+        fs::read_to_string(&temp.current).map_or(true, |content| content != temp.current_content);
+
+    if is_changed {
+        // A read-only file can't be written, but it can be replaced.
+        fs::remove_file(&temp.current).ok();
+        fs::write(&temp.current, &temp.current_content)?;
+        set_read_only(&temp.current)?;
+    }
+
+    Ok(is_changed)
+}
+
+/// Makes a file read-only, so that the editor warns before changing it.
+fn set_read_only(path: &Path) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(path, permissions)
+}
+
+/// Returns the comment lines at the top of the target file,
+/// reminding how the lines are read.
+fn get_header(count: usize, format: BufferFormat, on_conflict: ConflictPolicy) -> String {
+    let mut lines = vec![
+        "Edit the target names, one per line. The lines starting with # are ignored.".to_string(),
+        format!(
+            "{count} path{} listed. When a target exists: {}.",
+            if count > 1 { "s" } else { "" },
+            config::get_conflict_policy_name(on_conflict)
+        ),
+    ];
+
+    if format.layout == BufferLayout::SingleFile {
+        lines.push("Each current name is in a comment above its target name.".to_string());
+    }
+
+    lines.push(
+        match (format.line_ids, format.allow_delete) {
+            (false, _) => "The lines are matched by position: keep one line per path, in order.",
+            (true, false) => {
+                "The lines are matched by ID: they can be reordered, and a removed line leaves its path unchanged."
+            }
+            (true, true) => {
+                "The lines are matched by ID: they can be reordered, and a removed line deletes its path."
+            }
+        }
+        .to_string(),
+    );
+
+    if format.allow_delete {
+        lines.push(format!(
+            "An emptied line, or one replaced by {DELETE_PREFIX}, deletes its path."
+        ));
+    }
+
+    lines
+        .iter()
+        .map(|line| format!("{COMMENT_PREFIX} {line}\n"))
+        .collect()
+}
+
+/// Writes the validation errors in the target file, as comments above their lines,
+/// instead of the errors of the previous try. An error that isn't about a line
+/// is written at the top.
+pub fn write_errors_in_target_file(
+    temp: &TempEditingFiles,
+    line_errors: &[Option<String>],
+    error: Option<&str>,
+) -> io::Result<()> {
+    let content = fs::read_to_string(&temp.target)?;
+    let mut annotated = String::new();
+    let mut index = 0;
+
+    if let Some(error) = error {
+        annotated.push_str(&format!("{ERROR_PREFIX} {error}\n"));
+    }
+
+    for line in content.strip_prefix('\u{feff}').unwrap_or(&content).lines() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        if line.starts_with(ERROR_PREFIX) {
+            continue;
+        }
+
+        if !line.starts_with(COMMENT_PREFIX) {
+            let line_index = if temp.format.line_ids {
+                validation::split_line_id(line)
+                    .ok()
+                    .map(|(id, _)| id.wrapping_sub(1))
+            } else {
+                Some(index)
+            };
+
+            if let Some(Some(error)) = line_index.and_then(|index| line_errors.get(index)) {
+                annotated.push_str(&format!("{ERROR_PREFIX} {error}\n"));
+            }
+
+            index += 1;
+        }
+
+        annotated.push_str(line);
+        annotated.push('\n');
+    }
+
+    fs::write(&temp.target, annotated)
+}
+
 /// Make sure the temporary files are deleted after use.
 pub fn clean_temporary_files(temp: TempEditingFiles) {
     temp.dir.close().expect("Couldn't close the temp dir");
@@ -57,33 +262,409 @@ fn create_temporary_file(
     Ok(file_path)
 }
 
-/// Read a list of paths from a file (or panics if it can't be read).
-pub fn read_paths_from(path: &Path) -> Vec<PathBuf> {
-    let file = File::open(path).expect(format!("The file {:?} couldn't be read.", path).as_str());
-    let buffer = std::io::BufReader::new(file);
+/// Read the lines of escaped paths from a file (or panics if it can't be read),
+/// without the comment lines.
+/// The lines aren't trimmed, but the CRLF line endings and the BOM some editors add are removed.
+pub fn read_lines_from(path: &Path) -> Vec<String> {
+    let content = fs::read_to_string(path)
+        .unwrap_or_else(|err| panic!("The file {:?} couldn't be read: {}", path, err));
 
-    buffer
+    content
+        .strip_prefix('\u{feff}')
+        .unwrap_or(&content)
         .lines()
-        .map(|line| {
-            let line = line.expect("An error occured reading a line of the file.");
-            PathBuf::from(line.trim())
-        })
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+        .filter(|line| !line.starts_with(COMMENT_PREFIX))
+        .map(str::to_string)
         .collect()
 }
 
-// Apply the given renamings and returns the results
-pub fn apply_renamings(renamings: Vec<Renaming>) -> Vec<(Renaming, io::Result<()>)> {
-    renamings
-        .into_iter()
-        .map(|renaming| {
-            let result = rename(&renaming);
-            (renaming, result)
+/// How a step was applied
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+
+    /// The target is on another file system,
+    /// so the path was copied there and then deleted
+    CopiedAcrossDevices,
+}
+
+/// Results of a batch of renamings
+pub struct RenamingsResults {
+    /// Result of each step, in the order they were applied
+    pub results: Vec<(Step, io::Result<Outcome>)>,
+
+    /// Result of each step reverted after a failure, if a rollback occured
+    pub rollback: Option<Vec<(Step, io::Result<Outcome>)>>,
+
+    /// Folders left empty by the batch and removed afterwards
+    pub pruned: Vec<(PathBuf, io::Result<()>)>,
+}
+
+/// Apply the given steps and returns the results.
+/// In atomic mode, the first failure stops the batch
+/// and the steps already done are reverted in reverse order.
+/// Each step is written to the journal before being applied.
+pub fn apply_renamings(steps: Vec<Step>, atomic: bool) -> Result<RenamingsResults, String> {
+    let mut journal = Journal::start(&steps)?;
+    let mut results = vec![];
+
+    for (index, step) in steps.into_iter().enumerate() {
+        let result = journal
+            .write(&Entry::Begin(index))
+            .and_then(|()| apply_step(&step));
+
+        journal.write_outcome(&result);
+
+        let failed = result.is_err();
+
+        results.push((step, result));
+
+        if atomic && failed {
+            let rollback = rollback_steps(&results, &mut journal);
+
+            if rollback.iter().all(|(_, result)| result.is_ok()) {
+                journal.close();
+            }
+
+            return Ok(RenamingsResults {
+                results,
+                rollback: Some(rollback),
+                pruned: vec![],
+            });
+        }
+    }
+
+    journal.close();
+
+    Ok(RenamingsResults {
+        results,
+        rollback: None,
+        pruned: vec![],
+    })
+}
+
+/// Removes the folders the moved paths came from if they're now empty,
+/// and then their parents, up to the current directory (which is kept).
+pub fn prune_empty_folders(results: &mut RenamingsResults) {
+    let Ok(cwd) = std::env::current_dir().and_then(|cwd| cwd.canonicalize()) else {
+        return;
+    };
+
+    let mut folders: Vec<PathBuf> = results
+        .results
+        .iter()
+        .filter(|(_, result)| result.is_ok())
+        .filter_map(|(step, _)| match step {
+            Step::Delete(path) => Some(path),
+            step => step.get_move().map(|(current, _)| current),
+        })
+        .filter_map(|path| path.parent())
+        .filter_map(|folder| cwd.join(folder).canonicalize().ok())
+        .collect();
+
+    // The deepest folders first, so that their parents are empty when they're reached.
+    folders.sort_by_key(|folder| std::cmp::Reverse(folder.components().count()));
+    folders.dedup();
+
+    for folder in folders {
+        let ancestors = folder
+            .ancestors()
+            .take_while(|ancestor| ancestor.starts_with(&cwd) && *ancestor != cwd);
+
+        for ancestor in ancestors {
+            if !is_empty_folder(ancestor) {
+                break;
+            }
+
+            let result = fs::remove_dir(ancestor);
+            let failed = result.is_err();
+
+            let path = ancestor.strip_prefix(&cwd).unwrap_or(ancestor);
+            results.pruned.push((path.to_path_buf(), result));
+
+            if failed {
+                break;
+            }
+        }
+    }
+}
+
+/// Whether a path is a folder without anything in it.
+fn is_empty_folder(path: &Path) -> bool {
+    fs::read_dir(path)
+        .map(|mut entries| entries.next().is_none())
+        .unwrap_or(false)
+}
+
+/// Reverts the successful steps, from the last one to the first one.
+fn rollback_steps(
+    results: &[(Step, io::Result<Outcome>)],
+    journal: &mut Journal,
+) -> Vec<(Step, io::Result<Outcome>)> {
+    results
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, (_, result))| result.is_ok())
+        .map(|(index, (step, _))| match step.reverse() {
+            Some(reverse) => {
+                let result = journal
+                    .write(&Entry::Revert(index))
+                    .and_then(|()| apply_step(&reverse));
+
+                journal.write_outcome(&result);
+
+                (reverse, result)
+            }
+            None => (step.clone(), Err(get_irreversible_error())),
         })
         .collect()
 }
 
+/// Error of a step that can't be reverted
+pub fn get_irreversible_error() -> io::Error {
+    io::Error::other("a deletion can't be reverted")
+}
+
+/// Apply one step and returns the result.
+pub fn apply_step(step: &Step) -> io::Result<Outcome> {
+    match step {
+        Step::Rename(renaming) => rename(renaming),
+        Step::GitRename(renaming) => {
+            let outcome = rename(renaming)?;
+            git::move_in_index(renaming)?;
+            Ok(outcome)
+        }
+        Step::Trash(renaming) => trash::move_to_trash(renaming),
+        Step::Restore(renaming) => trash::restore_from_trash(renaming),
+        Step::Delete(path) => remove_path(path).map(|()| Outcome::Done),
+        Step::Copy((current, target)) => {
+            create_parent_folder(target)?;
+            copy_into_place(current, target, false).map(|()| Outcome::Done)
+        }
+        Step::Symlink((content, link)) => {
+            create_parent_folder(link)?;
+            create_symlink(content, link).map(|()| Outcome::Done)
+        }
+        Step::HardLink((current, link)) => {
+            create_parent_folder(link)?;
+            fs::hard_link(current, link).map(|()| Outcome::Done)
+        }
+    }
+}
+
+/// Creates the missing parent folders of a path.
+fn create_parent_folder(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+/// Copies a path next to its target, checks the copy if asked, and renames it to the target,
+/// so that the target only exists once the copy is complete.
+/// If anything goes wrong, the partial copy is deleted.
+fn copy_into_place(current: &Path, target: &Path, verify: bool) -> io::Result<()> {
+    let partial = get_partial_copy_path(target);
+
+    let result = copy_path(current, &partial)
+        .and_then(|()| {
+            if verify {
+                verify_copy(current, &partial)
+            } else {
+                Ok(())
+            }
+        })
+        .and_then(|()| rename(&(partial.clone(), target.to_path_buf())).map(|_| ()));
+
+    if result.is_err() {
+        remove_path(&partial).ok();
+    }
+
+    result
+}
+
+/// Cleans up after a step interrupted by a crash, once it's known whether it was applied:
+/// its partial copy is deleted, and so is the source of a move across file systems
+/// whose copy was complete.
+pub fn clean_interrupted_step(step: &Step, is_applied: bool) -> io::Result<()> {
+    let target = match step {
+        Step::Copy((_, target)) => target,
+        step => match step.get_move() {
+            Some((current, target)) => {
+                if is_applied && current.symlink_metadata().is_ok() {
+                    remove_path(current)?;
+                }
+
+                target
+            }
+            None => return Ok(()),
+        },
+    };
+
+    let partial = get_partial_copy_path(target);
+
+    if partial.symlink_metadata().is_ok() {
+        remove_path(&partial)?;
+    }
+
+    Ok(())
+}
+
+/// Copies a file, a symbolic link, or a folder with all its content,
+/// keeping their permissions, modification times and extended attributes.
+fn copy_path(current: &Path, target: &Path) -> io::Result<()> {
+    let metadata = current.symlink_metadata()?;
+
+    if metadata.is_symlink() {
+        return create_symlink(&fs::read_link(current)?, target);
+    }
+
+    if metadata.is_dir() {
+        fs::create_dir(target)?;
+
+        for entry in fs::read_dir(current)? {
+            let entry = entry?;
+            copy_path(&entry.path(), &target.join(entry.file_name()))?;
+        }
+    } else if target.symlink_metadata().is_ok() {
+        // `fs::copy` replaces an existing file.
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "the target already exists",
+        ));
+    } else {
+        fs::copy(current, target)?;
+    }
+
+    copy_extended_attributes(current, target)?;
+
+    // The folder's permissions are set after its content is copied, in case it's read-only.
+    fs::set_permissions(target, metadata.permissions())?;
+
+    let times = fs::FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+
+    File::open(target)?.set_times(times)
+}
+
+/// Copies the extended attributes the target's file system supports.
+#[cfg(unix)]
+fn copy_extended_attributes(current: &Path, target: &Path) -> io::Result<()> {
+    let names = match xattr::list(current) {
+        Ok(names) => names,
+        Err(err) if err.kind() == io::ErrorKind::Unsupported => return Ok(()),
+        Err(err) => return Err(err),
+    };
+
+    for name in names {
+        if let Some(value) = xattr::get(current, &name)? {
+            match xattr::set(target, &name, &value) {
+                Err(err) if err.kind() != io::ErrorKind::Unsupported => return Err(err),
+                _ => (),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_extended_attributes(_current: &Path, _target: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Checks that a copy has the same content as the original, folders included.
+fn verify_copy(current: &Path, target: &Path) -> io::Result<()> {
+    let metadata = current.symlink_metadata()?;
+    let copy_metadata = target.symlink_metadata()?;
+
+    let is_same = if metadata.is_symlink() {
+        copy_metadata.is_symlink() && fs::read_link(current)? == fs::read_link(target)?
+    } else if metadata.is_dir() {
+        let mut count = 0;
+
+        for entry in fs::read_dir(current)? {
+            let entry = entry?;
+            verify_copy(&entry.path(), &target.join(entry.file_name()))?;
+            count += 1;
+        }
+
+        copy_metadata.is_dir() && fs::read_dir(target)?.count() == count
+    } else {
+        copy_metadata.is_file()
+            && copy_metadata.len() == metadata.len()
+            && have_same_content(current, target)?
+    };
+
+    if is_same {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "the copy {:?} is different from the original",
+            target
+        )))
+    }
+}
+
+/// Whether two files have the same content.
+fn have_same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    let mut a = io::BufReader::new(File::open(a)?);
+    let mut b = io::BufReader::new(File::open(b)?);
+
+    loop {
+        let (a_buffer, b_buffer) = (a.fill_buf()?, b.fill_buf()?);
+        let length = a_buffer.len().min(b_buffer.len());
+
+        if length == 0 {
+            return Ok(a_buffer.is_empty() && b_buffer.is_empty());
+        }
+
+        if a_buffer[..length] != b_buffer[..length] {
+            return Ok(false);
+        }
+
+        a.consume(length);
+        b.consume(length);
+    }
+}
+
+/// Creates a symbolic link at `link` containing `content`.
+#[cfg(unix)]
+fn create_symlink(content: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(content, link)
+}
+
+/// Creates a symbolic link at `link` containing `content`.
+#[cfg(windows)]
+fn create_symlink(content: &Path, link: &Path) -> io::Result<()> {
+    let points_to_folder = link
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(content)
+        .is_dir();
+
+    if points_to_folder {
+        std::os::windows::fs::symlink_dir(content, link)
+    } else {
+        std::os::windows::fs::symlink_file(content, link)
+    }
+}
+
+/// Deletes a file, a symbolic link, or a folder with all its content.
+fn remove_path(path: &Path) -> io::Result<()> {
+    if path.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
 // Apply one renaming and returns the result.
-fn rename(renaming: &Renaming) -> io::Result<()> {
+// A path can't be renamed to another file system, so it's copied and deleted instead.
+pub fn rename(renaming: &Renaming) -> io::Result<Outcome> {
     let path_to_target = renaming.1.parent();
 
     // Creates -if necessary- parent folder for the target path.
@@ -92,34 +673,104 @@ fn rename(renaming: &Renaming) -> io::Result<()> {
         std::fs::create_dir_all(path_to_target)?;
     }
 
-    fs::rename(&renaming.0, &renaming.1)?;
+    // `fs::rename` replaces an existing target: a target that wasn't freed,
+    // like when an earlier step of a chain failed, must be kept.
+    if renaming.1.symlink_metadata().is_ok() && !is_same_file(&renaming.0, &renaming.1) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "the target already exists",
+        ));
+    }
 
-    Ok(())
+    match fs::rename(&renaming.0, &renaming.1) {
+        Ok(()) => Ok(Outcome::Done),
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            move_across_devices(renaming)?;
+            Ok(Outcome::CopiedAcrossDevices)
+        }
+        Err(err) => Err(err),
+    }
 }
 
+/// Whether two paths are the same file, like the two cases of a name
+/// on a case-insensitive file system.
+#[cfg(unix)]
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (a.symlink_metadata(), b.symlink_metadata()) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Copies a path to another file system, checks the copy, and then deletes the path.
+/// If anything goes wrong before the deletion, the copy is deleted instead.
+fn move_across_devices((current, target): &Renaming) -> io::Result<()> {
+    if target.symlink_metadata().is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "the target already exists on the other file system",
+        ));
+    }
+
+    copy_into_place(current, target, true)?;
+    remove_path(current)
+}
+
+/// Path meaning that the paths are read from stdin
+pub const STDIN_PATH: &str = "-";
+
 /// Transforms a vector of paths patterns to the corresponding paths list.
-pub fn unwrap_paths_patterns(paths: Vec<String>) -> Vec<String> {
+pub fn unwrap_paths_patterns(paths: Vec<OsString>, listing: &ListingArgs) -> Vec<PathBuf> {
     let mut files_paths = vec![];
 
+    let options = MatchOptions {
+        require_literal_leading_dot: !listing.hidden,
+        ..MatchOptions::new()
+    };
+
     for path in paths {
-        let is_pattern = path.contains('?') | path.contains('*') | path.contains("**");
-
-        if is_pattern {
-            let parsed_paths = glob(path.as_str()).expect("Failed to read pattern");
-
-            for file in parsed_paths {
-                match file {
-                    Ok(path) => match path.to_str() {
-                        Some(path) => files_paths.push(path.to_string()),
-                        None => {
-                            println!("Couldn't convert to string {}", format!("{:?}", path).red());
-                        }
-                    },
-                    Err(e) => println!("{}", format!("{:?}", e).red()),
+        // A path that isn't valid UTF-8 can't be a pattern.
+        let pattern = path
+            .to_str()
+            .filter(|path| path.contains('?') | path.contains('*') | path.contains("**"));
+
+        // The paths read from stdin are taken as they are, they're not patterns.
+        let matched_paths = if path == STDIN_PATH {
+            read_paths_from_stdin(listing.null)
+        } else if let Some(pattern) = pattern {
+            match expand_pattern(pattern, options) {
+                Ok(matched_paths) => matched_paths,
+                Err(e) => {
+                    println!("{}", format!("Invalid pattern {pattern}: {e}").red());
+                    vec![]
                 }
             }
         } else {
-            files_paths.push(path);
+            vec![PathBuf::from(path)]
+        };
+
+        for path in matched_paths {
+            let listed_paths = if listing.recursive && path.is_dir() {
+                list_folder(&path, listing)
+            } else {
+                vec![path]
+            };
+
+            files_paths.extend(
+                listed_paths
+                    .into_iter()
+                    .filter(|path| is_listed(path, listing)),
+            );
         }
     }
 
@@ -128,3 +779,196 @@ pub fn unwrap_paths_patterns(paths: Vec<String>) -> Vec<String> {
 
     files_paths
 }
+
+/// Lists the paths matching a shell pattern, one component at a time.
+/// Unlike with `glob`, the names that aren't valid UTF-8 are listed too:
+/// they're matched by their lossy version.
+fn expand_pattern(pattern: &str, options: MatchOptions) -> Result<Vec<PathBuf>, PatternError> {
+    let mut paths = vec![PathBuf::new()];
+
+    for component in Path::new(pattern).components() {
+        let name = component.as_os_str().to_string_lossy();
+
+        paths = if name == "**" {
+            paths
+                .iter()
+                .flat_map(|folder| list_subfolders(folder, options))
+                .collect()
+        } else if !name.contains(['*', '?', '[']) {
+            paths
+                .into_iter()
+                .map(|path| path.join(component))
+                .filter(|path| path.symlink_metadata().is_ok())
+                .collect()
+        } else {
+            let component_pattern = Pattern::new(&name)?;
+
+            paths
+                .iter()
+                .flat_map(|folder| read_folder(folder))
+                .filter(|path| {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    component_pattern.matches_with(&name, options)
+                })
+                .collect()
+        };
+    }
+
+    paths.retain(|path| !path.as_os_str().is_empty());
+    Ok(paths)
+}
+
+/// Returns the paths in a folder, or nothing if it's not a folder.
+/// An empty path is the current directory.
+fn read_folder(folder: &Path) -> Vec<PathBuf> {
+    let dir = if folder.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        folder
+    };
+
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| folder.join(entry.file_name()))
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// Returns a folder and all its subfolders, for a `**` pattern.
+fn list_subfolders(folder: &Path, options: MatchOptions) -> Vec<PathBuf> {
+    let mut folders = vec![folder.to_path_buf()];
+
+    for path in read_folder(folder) {
+        let is_hidden = path
+            .file_name()
+            .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."));
+
+        if path
+            .symlink_metadata()
+            .is_ok_and(|metadata| metadata.is_dir())
+            && !(is_hidden && options.require_literal_leading_dot)
+        {
+            folders.extend(list_subfolders(&path, options));
+        }
+    }
+
+    folders
+}
+
+/// Whether a path passes the filters on the listed paths.
+fn is_listed(path: &Path, listing: &ListingArgs) -> bool {
+    let Ok(metadata) = path.symlink_metadata() else {
+        // A path that doesn't exist is listed to be reported by the validation.
+        return true;
+    };
+
+    let is_dir = metadata.is_dir();
+
+    let path_type = if metadata.is_symlink() {
+        PathType::Symlink
+    } else if is_dir {
+        PathType::Folder
+    } else {
+        PathType::File
+    };
+
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+
+    let is_excluded = path
+        .ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .any(|ancestor| {
+            listing.exclude.iter().any(|pattern| {
+                pattern.matches_with(&ancestor.to_string_lossy(), options)
+                    || (!pattern.as_str().contains('/')
+                        && ancestor.file_name().is_some_and(|name| {
+                            pattern.matches_with(&name.to_string_lossy(), options)
+                        }))
+            })
+        });
+
+    let modified = metadata.modified().ok();
+    let is_modified_in_range = listing
+        .newer
+        .is_none_or(|date| modified.is_some_and(|modified| modified > date))
+        && listing
+            .older
+            .is_none_or(|date| modified.is_some_and(|modified| modified < date));
+
+    // A folder has no size of its own, so it's skipped by the size filters.
+    let has_size_filter = listing.min_size.is_some() || listing.max_size.is_some();
+    let is_size_in_range = !(has_size_filter && is_dir)
+        && listing.min_size.is_none_or(|size| metadata.len() >= size)
+        && listing.max_size.is_none_or(|size| metadata.len() <= size);
+
+    let is_type_listed = match path_type {
+        PathType::Folder => !listing.files_only,
+        _ => !listing.dirs_only,
+    } && listing
+        .path_type
+        .is_none_or(|expected| expected == path_type);
+
+    is_type_listed && !is_excluded && is_modified_in_range && is_size_in_range
+}
+
+/// Reads the paths from stdin, one per line or separated by NUL characters.
+fn read_paths_from_stdin(null_separated: bool) -> Vec<PathBuf> {
+    let mut input = vec![];
+
+    if let Err(err) = io::stdin().lock().read_to_end(&mut input) {
+        println!(
+            "{}",
+            format!("Couldn't read the paths from stdin: {}", err).red()
+        );
+        return vec![];
+    }
+
+    let separator = if null_separated { b'\0' } else { b'\n' };
+
+    input
+        .split(|byte| *byte == separator)
+        .filter(|path| !path.is_empty())
+        .map(|path| path_from_bytes(path.to_vec()))
+        .collect()
+}
+
+/// Lists a folder and its content, recursively. The current directory itself
+/// isn't listed, and `.git` folders are always skipped.
+fn list_folder(folder: &Path, listing: &ListingArgs) -> Vec<PathBuf> {
+    let respect_gitignore = listing.respect_gitignore;
+
+    let walk = WalkBuilder::new(folder)
+        .max_depth(listing.max_depth)
+        .hidden(!listing.hidden)
+        .parents(respect_gitignore)
+        .ignore(respect_gitignore)
+        .git_ignore(respect_gitignore)
+        .git_global(respect_gitignore)
+        .git_exclude(respect_gitignore)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+
+    let mut paths = vec![];
+
+    for entry in walk {
+        match entry {
+            Ok(entry) if entry.depth() == 0 && folder == Path::new(".") => (),
+            Ok(entry) => {
+                let path = entry.into_path();
+                paths.push(
+                    path.strip_prefix(".")
+                        .map(Path::to_path_buf)
+                        .unwrap_or(path),
+                );
+            }
+            Err(e) => println!("{}", e.to_string().red()),
+        }
+    }
+
+    paths
+}
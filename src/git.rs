@@ -0,0 +1,441 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use crate::escaping::path_from_bytes;
+use crate::validation::Renaming;
+
+/// Runs a git command in a directory, and returns its output.
+fn git<I, S>(dir: &Path, args: I) -> io::Result<Output>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+}
+
+/// Runs a git command in a directory with some input, and returns its output.
+fn git_with_input<I, S>(dir: &Path, args: I, input: &[u8]) -> io::Result<Output>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    child
+        .stdin
+        .take()
+        .expect("The stdin of git is piped")
+        .write_all(input)?;
+
+    child.wait_with_output()
+}
+
+/// Returns the nearest existing folder containing a path.
+fn get_existing_folder(path: &Path) -> PathBuf {
+    path.ancestors()
+        .skip(1)
+        .find(|folder| folder.is_dir())
+        .map(Path::to_path_buf)
+        .unwrap_or_default()
+}
+
+/// Whether the current directory is inside a git work tree.
+pub fn is_in_work_tree() -> bool {
+    git(Path::new("."), ["rev-parse", "--is-inside-work-tree"])
+        .map(|output| output.status.success() && output.stdout.starts_with(b"true"))
+        .unwrap_or(false)
+}
+
+/// What git knows about the paths of a batch, read with one command each
+/// instead of one per path. The paths are relative to the top of the work tree,
+/// and the folders containing a tracked or changed file are included.
+pub struct WorkTree {
+    top: PathBuf,
+    tracked: HashSet<PathBuf>,
+    ignored: HashSet<PathBuf>,
+    changed: HashSet<PathBuf>,
+}
+
+impl WorkTree {
+    /// Reads the tracked paths of the work tree containing a folder,
+    /// which of the `targets` are ignored, and the paths with uncommitted changes if asked.
+    /// Returns nothing outside of a work tree.
+    pub fn read(folder: &Path, targets: &[&Path], with_changes: bool) -> Option<WorkTree> {
+        let output = git(folder, ["rev-parse", "--show-toplevel"]).ok()?;
+
+        if !output.status.success() {
+            return None;
+        }
+
+        let top = String::from_utf8_lossy(&output.stdout);
+        let top = Path::new(top.trim_end()).canonicalize().ok()?;
+
+        let mut work_tree = WorkTree {
+            top,
+            tracked: HashSet::new(),
+            ignored: HashSet::new(),
+            changed: HashSet::new(),
+        };
+
+        let output = git(&work_tree.top, ["ls-files", "-z"]).ok()?;
+        work_tree.tracked = with_folders(split_paths(&output.stdout));
+
+        let targets: Vec<PathBuf> = targets
+            .iter()
+            .filter_map(|target| work_tree.get_relative_path(target))
+            .collect();
+
+        if !targets.is_empty() {
+            let mut input = vec![];
+
+            for target in &targets {
+                input.extend_from_slice(target.as_os_str().as_encoded_bytes());
+                input.push(0);
+            }
+
+            // `check-ignore` exits with 1 when no path is ignored.
+            let output =
+                git_with_input(&work_tree.top, ["check-ignore", "--stdin", "-z"], &input).ok()?;
+            work_tree.ignored = split_paths(&output.stdout).collect();
+        }
+
+        if with_changes {
+            let output = git(
+                &work_tree.top,
+                ["status", "--porcelain", "-z", "--untracked-files=no"],
+            )
+            .ok()?;
+
+            work_tree.changed = with_folders(split_status_paths(&output.stdout));
+        }
+
+        Some(work_tree)
+    }
+
+    /// Whether a file, or a file inside a folder, is tracked by git.
+    pub fn is_tracked(&self, path: &Path) -> bool {
+        self.contains(&self.tracked, path)
+    }
+
+    /// Whether a path, existing or not, is one of the targets ignored by git.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        self.contains(&self.ignored, path)
+    }
+
+    /// Whether a tracked file, or a tracked file inside a folder, has uncommitted changes.
+    pub fn has_uncommitted_changes(&self, path: &Path) -> bool {
+        self.contains(&self.changed, path)
+    }
+
+    fn contains(&self, paths: &HashSet<PathBuf>, path: &Path) -> bool {
+        self.get_relative_path(path)
+            .is_some_and(|path| paths.contains(&path))
+    }
+
+    /// Returns a path, existing or not, relative to the top of the work tree,
+    /// with its existing folders resolved like git does.
+    fn get_relative_path(&self, path: &Path) -> Option<PathBuf> {
+        let path = std::env::current_dir().ok()?.join(path);
+        let folder = get_existing_folder(&path);
+        let rest = path.strip_prefix(&folder).ok()?;
+
+        let path = folder.canonicalize().ok()?.join(rest);
+        let path = path.strip_prefix(&self.top).ok()?;
+
+        (!path.as_os_str().is_empty()).then(|| path.to_path_buf())
+    }
+}
+
+/// Splits an output of NUL-separated paths.
+fn split_paths(output: &[u8]) -> impl Iterator<Item = PathBuf> + '_ {
+    output
+        .split(|byte| *byte == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| path_from_bytes(path.to_vec()))
+}
+
+/// Splits the output of `git status --porcelain -z`, where each entry is `XY path`,
+/// followed by the original path of a renamed or copied entry.
+fn split_status_paths(output: &[u8]) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    let mut entries = output.split(|byte| *byte == 0);
+
+    while let Some(entry) = entries.next() {
+        let Some(path) = entry.get(3..) else {
+            continue;
+        };
+
+        paths.push(path_from_bytes(path.to_vec()));
+
+        if entry.starts_with(b"R") || entry.starts_with(b"C") {
+            paths.extend(entries.next().map(|path| path_from_bytes(path.to_vec())));
+        }
+    }
+
+    paths
+}
+
+/// Returns the paths with all the folders containing them.
+fn with_folders(paths: impl IntoIterator<Item = PathBuf>) -> HashSet<PathBuf> {
+    let mut all = HashSet::new();
+
+    for path in paths {
+        for ancestor in path.ancestors() {
+            if ancestor.as_os_str().is_empty() || !all.insert(ancestor.to_path_buf()) {
+                break;
+            }
+        }
+    }
+
+    all
+}
+
+/// Returns the path relative to the top of its work tree, like `src/main.rs`,
+/// and the top of the work tree.
+fn get_path_in_work_tree(path: &Path) -> io::Result<(PathBuf, PathBuf)> {
+    let folder = get_existing_folder(&std::env::current_dir()?.join(path));
+    let output = git(&folder, ["rev-parse", "--show-toplevel", "--show-prefix"])?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+
+    match (output.status.success(), lines.next(), lines.next()) {
+        (true, Some(top), prefix) => {
+            let name = path.file_name().unwrap_or_default();
+            let path = Path::new(prefix.unwrap_or("")).join(name);
+
+            Ok((path, PathBuf::from(top)))
+        }
+        _ => Err(io::Error::other(format!(
+            "{:?} isn't in a git work tree",
+            path
+        ))),
+    }
+}
+
+/// Moves the index entries of a path that was just renamed, like `git mv` does:
+/// the entries keep their content, so the changes not staged stay so.
+pub fn move_in_index((current, target): &Renaming) -> io::Result<()> {
+    let (current, top) = get_path_in_work_tree(current)?;
+    let (target, target_top) = get_path_in_work_tree(target)?;
+
+    if top != target_top {
+        return Err(io::Error::other("the target is in another git work tree"));
+    }
+
+    let output = git(
+        &top,
+        [
+            OsStr::new("ls-files"),
+            OsStr::new("--stage"),
+            OsStr::new("-z"),
+            OsStr::new("--"),
+            current.as_os_str(),
+        ],
+    )?;
+
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    // Each entry is `<mode> <object> <stage>\t<path>`,
+    // it's removed with a mode 0 and added again at the new path.
+    let mut index_info = vec![];
+
+    for entry in output.stdout.split(|byte| *byte == 0) {
+        let Some(tab) = entry.iter().position(|byte| *byte == b'\t') else {
+            continue;
+        };
+
+        let (info, path) = (&entry[..tab], path_from_bytes(entry[tab + 1..].to_vec()));
+        let info = String::from_utf8_lossy(info);
+
+        let Some((mode, object)) = info.split_once(' ') else {
+            continue;
+        };
+        let object = object.split(' ').next().unwrap_or_default();
+
+        let moved = match path.strip_prefix(&current) {
+            Ok(rest) if rest.as_os_str().is_empty() => target.clone(),
+            Ok(rest) => target.join(rest),
+            Err(_) => continue,
+        };
+
+        for (mode, path) in [("0", &path), (mode, &moved)] {
+            index_info.extend_from_slice(format!("{mode} {object}\t").as_bytes());
+            index_info.extend_from_slice(path.as_os_str().as_encoded_bytes());
+            index_info.push(0);
+        }
+    }
+
+    let output = git_with_input(&top, ["update-index", "-z", "--index-info"], &index_info)?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "couldn't update the git index: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::{self, ValidationError, ValidationRules};
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Creates a repository with its files committed, and a `.gitignore` ignoring `*.log`.
+    fn init_repo(files: &[&str]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+
+        for file in files {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, file).unwrap();
+        }
+
+        fs::write(dir.path().join(".gitignore"), "*.log\n").unwrap();
+
+        run(&dir, &["init", "--quiet"]);
+        run(&dir, &["add", "--all"]);
+        run(
+            &dir,
+            &[
+                "-c",
+                "user.name=diffren",
+                "-c",
+                "user.email=diffren@localhost",
+                "commit",
+                "--quiet",
+                "--message=init",
+            ],
+        );
+
+        dir
+    }
+
+    /// Runs a git command in the repository, and returns its output.
+    fn run(dir: &TempDir, args: &[&str]) -> String {
+        let output = git(dir.path(), args).unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// Renames a path in the repository and moves its index entries.
+    fn rename_in_repo(dir: &TempDir, current: &str, target: &str) {
+        let renaming = (dir.path().join(current), dir.path().join(target));
+
+        fs::rename(&renaming.0, &renaming.1).unwrap();
+        move_in_index(&renaming).unwrap();
+    }
+
+    #[test]
+    fn moves_a_file_in_the_index() {
+        let dir = init_repo(&["a.txt"]);
+
+        // The change not staged must stay so.
+        fs::write(dir.path().join("a.txt"), "changed").unwrap();
+        rename_in_repo(&dir, "a.txt", "b.txt");
+
+        assert_eq!(run(&dir, &["status", "--porcelain"]), "RM a.txt -> b.txt\n");
+    }
+
+    #[test]
+    fn moves_a_folder_with_its_tracked_children_in_the_index() {
+        let dir = init_repo(&["A/x.txt", "A/sub/y.txt"]);
+        rename_in_repo(&dir, "A", "B");
+
+        assert_eq!(
+            run(&dir, &["ls-files"]),
+            ".gitignore\nB/sub/y.txt\nB/x.txt\n"
+        );
+        assert_eq!(
+            run(&dir, &["status", "--porcelain"]),
+            "R  A/sub/y.txt -> B/sub/y.txt\nR  A/x.txt -> B/x.txt\n"
+        );
+    }
+
+    #[test]
+    fn moves_a_name_with_spaces_in_the_index() {
+        let dir = init_repo(&["my file.txt"]);
+        rename_in_repo(&dir, "my file.txt", "new name.txt");
+
+        assert_eq!(run(&dir, &["ls-files", "-z"]), ".gitignore\0new name.txt\0");
+    }
+
+    #[test]
+    fn tells_tracked_and_ignored_paths() {
+        let dir = init_repo(&["a.txt", "A/b.txt"]);
+        let paths =
+            ["a.log", "missing/a.log", "a.txt", "my file.log"].map(|path| dir.path().join(path));
+        let targets: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+
+        let work_tree = WorkTree::read(dir.path(), &targets, false).unwrap();
+
+        assert!(work_tree.is_ignored(&paths[0]));
+        assert!(work_tree.is_ignored(&paths[1]));
+        assert!(!work_tree.is_ignored(&paths[2]));
+        assert!(work_tree.is_ignored(&paths[3]));
+
+        assert!(work_tree.is_tracked(&dir.path().join("a.txt")));
+        assert!(work_tree.is_tracked(&dir.path().join("A")));
+        assert!(!work_tree.is_tracked(&dir.path().join("a.log")));
+    }
+
+    #[test]
+    fn refuses_to_move_a_tracked_path_to_an_ignored_one() {
+        let dir = init_repo(&["a.txt", "b.txt"]);
+        let rules = ValidationRules {
+            git: true,
+            ..Default::default()
+        };
+
+        let renamings = vec![
+            (dir.path().join("a.txt"), dir.path().join("a.log")),
+            (dir.path().join("b.txt"), dir.path().join("c.txt")),
+        ];
+
+        let (validations, changes_count, error_count) =
+            validation::validate_renamings(renamings, &rules);
+
+        assert_eq!((changes_count, error_count), (1, 1));
+        assert!(matches!(
+            validations[0],
+            Err(ValidationError::TargetIsIgnored(_))
+        ));
+    }
+
+    #[test]
+    fn tells_uncommitted_changes() {
+        let dir = init_repo(&["a.txt", "b.txt", "A/c.txt"]);
+        fs::write(dir.path().join("a.txt"), "changed").unwrap();
+        fs::write(dir.path().join("A/c.txt"), "changed").unwrap();
+
+        let work_tree = WorkTree::read(dir.path(), &[], true).unwrap();
+
+        assert!(work_tree.has_uncommitted_changes(&dir.path().join("a.txt")));
+        assert!(work_tree.has_uncommitted_changes(&dir.path().join("A")));
+        assert!(!work_tree.has_uncommitted_changes(&dir.path().join("b.txt")));
+    }
+}
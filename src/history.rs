@@ -0,0 +1,233 @@
+use chrono::{DateTime, Local};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::config;
+use crate::display::{display_results, display_validations};
+use crate::escaping::{escape_path, serde_path, serde_renamings};
+use crate::filesystem::{apply_renamings, RenamingsResults};
+use crate::interaction::ask_user_to_confirm;
+use crate::planning::{get_moved_path, plan_changes, Operation, Step};
+use crate::trash;
+use crate::validation::{self, Renaming, ValidationRules};
+
+/// A batch of renamings applied together
+#[derive(Serialize, Deserialize)]
+pub struct Batch {
+    /// When the batch was applied
+    pub date: DateTime<Local>,
+
+    /// Working directory the paths are relative to
+    #[serde(with = "serde_path")]
+    pub cwd: PathBuf,
+
+    /// Paths that were moved, as (current, target) pairs
+    #[serde(with = "serde_renamings")]
+    pub renamings: Vec<Renaming>,
+}
+
+/// Reads the history, from the oldest batch to the most recent one.
+pub fn read_history() -> Result<Vec<Batch>, String> {
+    let path = config::get_path_to_history()?;
+
+    match fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|err| format!("Couldn't read the history {:?}: {}", path, err)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(format!("Couldn't read the history {:?}: {}", path, err)),
+    }
+}
+
+/// Saves the whole history.
+fn save_history(history: &[Batch]) -> Result<(), String> {
+    let path = config::get_path_to_history()?;
+
+    let content = serde_json::to_string_pretty(history)
+        .map_err(|err| format!("Couldn't serialize the history: {}", err))?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|err| format!("Couldn't create the directory {:?}: {}", parent, err))?;
+    }
+
+    fs::write(&path, content)
+        .map_err(|err| format!("Couldn't save the history {:?}: {}", path, err))
+}
+
+/// Adds the paths moved by a batch of renamings to the history.
+pub fn record_batch(results: &RenamingsResults) -> Result<(), String> {
+    let renamings = get_moved_paths(results);
+
+    if renamings.is_empty() {
+        return Ok(());
+    }
+
+    let cwd = std::env::current_dir()
+        .map_err(|err| format!("Couldn't get the current directory: {}", err))?;
+
+    let mut history = read_history()?;
+
+    history.push(Batch {
+        date: Local::now(),
+        cwd,
+        renamings,
+    });
+
+    save_history(&history)
+}
+
+/// Follows each path through the successful steps (temporary names, trash and
+/// rollback included) and returns the ones that ended up somewhere else.
+/// Paths deleted for good can't be undone, so they're left out.
+fn get_moved_paths(results: &RenamingsResults) -> Vec<Renaming> {
+    let mut moves: Vec<Renaming> = vec![];
+
+    let steps = results
+        .results
+        .iter()
+        .chain(results.rollback.iter().flatten());
+
+    for (step, result) in steps {
+        if result.is_err() {
+            continue;
+        }
+
+        let (from, to) = match (step, step.get_move()) {
+            (_, Some(path_move)) => path_move,
+            (Step::Delete(path), None) => {
+                moves.retain(|(_, location)| location != path);
+                continue;
+            }
+            // Copies and links leave the paths where they are.
+            (_, None) => continue,
+        };
+
+        match moves.iter_mut().find(|(_, location)| location == from) {
+            Some(path_move) => path_move.1 = to.clone(),
+            None => {
+                // A path inside a folder moved earlier is known by its path from before.
+                let origin = moves
+                    .iter()
+                    .find_map(|(origin, location)| get_moved_path(from, location, origin))
+                    .unwrap_or_else(|| from.clone());
+
+                moves.push((origin, to.clone()));
+            }
+        }
+
+        // The paths moved inside a folder move with it.
+        for (_, location) in moves.iter_mut() {
+            if let Some(moved) = get_moved_path(location, from, to) {
+                *location = moved;
+            }
+        }
+    }
+
+    moves.retain(|(origin, location)| origin != location);
+    moves
+}
+
+/// Lists the batches in the history, the most recent first.
+pub fn print_history() -> Result<(), String> {
+    let history = read_history()?;
+
+    if history.is_empty() {
+        println!("The history is empty.");
+        return Ok(());
+    }
+
+    for (number, batch) in history.iter().rev().enumerate() {
+        let count = batch.renamings.len();
+
+        println!(
+            "{} {} {count} path{} renamed in {}",
+            format!("{}.", number + 1).bold(),
+            batch.date.format("%Y-%m-%d %H:%M:%S").to_string().bold(),
+            if count > 1 { "s" } else { "" },
+            escape_path(&batch.cwd).italic()
+        );
+
+        for (current, target) in &batch.renamings {
+            println!(
+                "   {} → {}",
+                escape_path(current).bright_black(),
+                escape_path(target)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Reverts the nth most recent batch, if its paths are still where it left them.
+pub fn undo_batch(number: usize) -> Result<(), String> {
+    let mut history = read_history()?;
+
+    if number == 0 || number > history.len() {
+        return Err(format!("There is no batch number {number} in the history."));
+    }
+
+    let index = history.len() - number;
+    let batch = &history[index];
+
+    std::env::set_current_dir(&batch.cwd)
+        .map_err(|err| format!("Couldn't go to the directory {:?}: {}", batch.cwd, err))?;
+
+    println!(
+        "Undoing the renamings from {} in {}",
+        batch.date.format("%Y-%m-%d %H:%M:%S").to_string().bold(),
+        escape_path(&batch.cwd).italic()
+    );
+
+    let reverse = batch
+        .renamings
+        .iter()
+        .map(|(current, target)| (target.clone(), current.clone()))
+        .collect();
+
+    let (validations, _, error_count) =
+        validation::validate_renamings(reverse, &ValidationRules::default());
+
+    display_validations(&validations, Operation::Move);
+
+    if error_count > 0 {
+        return Err(
+            "Some paths have changed since this batch was applied, it can't be undone.".to_string(),
+        );
+    }
+
+    if !ask_user_to_confirm("Do you confirm you want to undo these renamings?") {
+        println!("\nExiting...");
+        return Ok(());
+    }
+
+    let changes = validation::keep_valid_changes(validations);
+
+    let steps = plan_changes(changes, Operation::Move, false)
+        .map_err(|err| format!("Couldn't plan the changes: {err}"))?
+        .into_iter()
+        .map(restore_trashed_path)
+        .collect();
+
+    let results = apply_renamings(steps, true)?;
+
+    display_results(&results);
+
+    if results.rollback.is_none() {
+        history.remove(index);
+        save_history(&history)?;
+    }
+
+    Ok(())
+}
+
+/// Turns the renaming of a path out of the trash into its restoration.
+fn restore_trashed_path(step: Step) -> Step {
+    match step {
+        Step::Rename((from, to)) if trash::is_in_trash(&from) => Step::Restore((from, to)),
+        step => step,
+    }
+}
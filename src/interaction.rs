@@ -1,53 +1,166 @@
 use crate::config;
-use crate::display::display_validations;
+use crate::display::{display_changes_since_last_edit, display_validations, get_error_message};
 use crate::filesystem::TempEditingFiles;
-use crate::validation;
+use crate::planning::Operation;
+use crate::validation::{self, Change, Renaming, ValidationError, ValidationRules};
 use crate::*;
 use colored::Colorize;
+use dialoguer::console::Term;
 use dialoguer::theme::ColorfulTheme;
 use dialoguer::Select;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::fs::File;
+use std::io::{self, IsTerminal};
+use std::process::Child;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
 
 /// Next action to perform :
 pub enum NextAction {
     /// Confirm the changes
-    Confirm(Vec<Renaming>),
+    Confirm(Vec<Change>),
     Edit,
     Exit,
 }
 
+/// What to do with a batch of renamings left unfinished
+pub enum RecoveryAction {
+    /// Apply the remaining renamings
+    Finish,
+    /// Revert the renamings already applied
+    Rollback,
+    /// Leave it for now
+    Ignore,
+}
+
 /// Opens the editor, and after the user inputs their changes,
 /// asks the user what to do next depending on the validation.
-pub fn ask_user_for_changes(temp: &TempEditingFiles) -> NextAction {
-    start_editor_and_wait(&temp);
+/// With `live`, the target names are validated each time they're saved meanwhile.
+/// The renamings of the previous round are given to show what changed since.
+pub fn ask_user_for_changes(
+    temp: &TempEditingFiles,
+    rules: &ValidationRules,
+    operation: Operation,
+    live: bool,
+    previous_renamings: &mut Vec<Renaming>,
+) -> NextAction {
+    if live {
+        start_editor_and_watch(temp, || display_saved_validations(temp, rules, operation));
+    } else {
+        start_editor_and_wait(temp);
+    }
+
+    // The current names are known, whatever the editor did to their file.
+    match filesystem::restore_current_file(temp) {
+        Ok(false) => (),
+        Ok(true) => println!(
+            "{}",
+            "The current names file was changed, only the target names are read.".yellow()
+        ),
+        Err(err) => println!(
+            "{}",
+            format!("Couldn't restore the current names file: {err}").red()
+        ),
+    }
+
+    let current = temp.current_lines();
+    let target = filesystem::read_lines_from(&temp.target);
+
+    let (action, line_errors, error) =
+        match validation::combine_paths_vecs(current, target, temp.format) {
+            Ok(renamings) => {
+                if !previous_renamings.is_empty() {
+                    display_changes_since_last_edit(previous_renamings, &renamings);
+                }
+
+                *previous_renamings = renamings.clone();
+
+                let (renamings, changes_count, error_count) =
+                    validation::validate_renamings(renamings, rules);
+
+                display_validations(&renamings, operation);
+
+                let line_errors: Vec<Option<String>> = renamings
+                    .iter()
+                    .map(|validation| match validation {
+                        Err(ValidationError::Unchanged(_)) | Ok(_) => None,
+                        Err(error) => Some(get_error_message(error)),
+                    })
+                    .collect();
+
+                let action = if error_count > 0 {
+                    println!(
+                        "\n{}",
+                        "There are errors in the paths renamings you requested."
+                            .bold()
+                            .red()
+                    );
+                    ask_user_to_retry()
+                } else if changes_count == 0 {
+                    println!("\n{}", "You requested no path renaming.".bold().red());
+                    ask_user_to_retry()
+                } else {
+                    ask_user_to_continue(validation::keep_valid_changes(renamings), operation)
+                };
+
+                (action, line_errors, None)
+            }
+            Err(message) => {
+                println!("{}\n", message.red());
+                (ask_user_to_retry(), vec![], Some(message))
+            }
+        };
+
+    // The errors are shown in the editor too, next to the lines to fix.
+    if let NextAction::Edit = action {
+        if let Err(err) =
+            filesystem::write_errors_in_target_file(temp, &line_errors, error.as_deref())
+        {
+            println!(
+                "{}",
+                format!("Couldn't write the errors in the target file: {err}").red()
+            );
+        }
+    }
+
+    action
+}
 
-    let current = filesystem::read_paths_from(&temp.current);
-    let target = filesystem::read_paths_from(&temp.target);
+/// Validates the target names saved while the editor is still open,
+/// and shows the result instead of the previous one.
+fn display_saved_validations(
+    temp: &TempEditingFiles,
+    rules: &ValidationRules,
+    operation: Operation,
+) {
+    Term::stdout().clear_screen().ok();
 
-    match validation::combine_paths_vecs(current, target) {
+    println!("{}", "Target names saved, the editor is still open.".bold());
+
+    let current = temp.current_lines();
+    let target = filesystem::read_lines_from(&temp.target);
+
+    match validation::combine_paths_vecs(current, target, temp.format) {
         Ok(renamings) => {
-            let (renamings, changes_count, error_count) = validation::validate_renamings(renamings);
+            let (renamings, changes_count, error_count) =
+                validation::validate_renamings(renamings, rules);
+
+            display_validations(&renamings, operation);
 
-            display_validations(&renamings);
+            let plural = |count| if count > 1 { "s" } else { "" };
 
             if error_count > 0 {
-                println!(
-                    "\n{}",
-                    "There are errors in the paths renamings you requested."
-                        .bold()
-                        .red()
-                );
-                ask_user_to_retry()
-            } else if changes_count == 0 {
-                println!("\n{}", "You requested no path renaming.".bold().red());
-                ask_user_to_retry()
+                let message = format!("{error_count} error{} to fix.", plural(error_count));
+                println!("\n{}", message.red());
             } else {
-                ask_user_to_continue(validation::keep_valid_renamings(renamings), changes_count)
+                let message = format!(
+                    "No errors, {changes_count} change{} to confirm once the editor is closed.",
+                    plural(changes_count)
+                );
+                println!("\n{}", message.green());
             }
         }
-        Err(message) => {
-            println!("{}\n", message.red());
-            ask_user_to_retry()
-        }
+        Err(message) => println!("\n{}", message.red()),
     }
 }
 
@@ -74,15 +187,34 @@ fn ask_user_to_retry() -> NextAction {
 }
 
 /// Asks the user to continue with current changes, retry editing or exit.
-fn ask_user_to_continue(changes: Vec<Renaming>, changes_count: u32) -> NextAction {
+fn ask_user_to_continue(changes: Vec<Change>, operation: Operation) -> NextAction {
+    let delete_count = changes
+        .iter()
+        .filter(|change| matches!(change, Change::Delete(_)))
+        .count();
+    let rename_count = changes.len() - delete_count;
+
+    let plural = |count: usize| if count > 1 { "s" } else { "" };
+
+    let question = match (rename_count, delete_count) {
+        (_, 0) => format!(
+            "{} {rename_count} path{}",
+            operation.verb(),
+            plural(rename_count)
+        ),
+        (0, _) => format!("delete {delete_count} path{}", plural(delete_count)),
+        _ => format!(
+            "rename {rename_count} path{} and delete {delete_count} path{}",
+            plural(rename_count),
+            plural(delete_count)
+        ),
+    };
+
     println!(
         "\n{}",
-        format!(
-            "Do you confirm you want to rename {changes_count} path{}?",
-            if changes_count > 1 { "s" } else { "" }
-        )
-        .bold()
-        .green()
+        format!("Do you confirm you want to {question}?")
+            .bold()
+            .green()
     );
 
     let items = vec!["Confirm", "Edit", "Quit"];
@@ -104,15 +236,123 @@ fn ask_user_to_continue(changes: Vec<Renaming>, changes_count: u32) -> NextActio
     }
 }
 
+/// Asks the user to confirm an action or quit.
+pub fn ask_user_to_confirm(question: &str) -> bool {
+    println!("\n{}", question.bold().green());
+
+    let items = vec!["Confirm", "Quit"];
+
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .items(&items)
+        .default(0)
+        .interact()
+        .unwrap_or(1);
+
+    println!("> {}", items[selection]);
+
+    selection == 0
+}
+
+/// Asks the user what to do with a batch of renamings left unfinished.
+pub fn ask_user_to_recover() -> RecoveryAction {
+    println!(
+        "{}",
+        "Do you want to finish this batch or roll it back?".red()
+    );
+
+    let items = vec!["Finish", "Roll back", "Quit"];
+
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .items(&items)
+        .default(0)
+        .interact()
+        .unwrap_or(2);
+
+    let answer = items[selection];
+
+    println!("> {}", items[selection]);
+
+    match answer {
+        "Finish" => RecoveryAction::Finish,
+        "Roll back" => RecoveryAction::Rollback,
+        _ => RecoveryAction::Ignore,
+    }
+}
+
 /// Starts the editor and waits for it to be closed.
 fn start_editor_and_wait(files: &TempEditingFiles) {
+    spawn_editor(files)
+        .wait()
+        .expect("An error occured waiting for the editor process.");
+}
+
+/// Starts the editor. When the paths were piped to stdin,
+/// the editor reads from the terminal instead, like the prompts do.
+fn spawn_editor(files: &TempEditingFiles) -> Child {
     let mut edit_cmd = config::get_editor_command(files);
 
-    let mut child = edit_cmd
+    if !io::stdin().is_terminal() {
+        if let Ok(tty) = File::open("/dev/tty") {
+            edit_cmd.stdin(tty);
+        }
+    }
+
+    edit_cmd
         .spawn()
-        .expect("The editor process couldn't be spawned.");
+        .expect("The editor process couldn't be spawned.")
+}
 
-    child
-        .wait()
-        .expect("An error occured waiting for the editor process.");
+/// Starts the editor like `start_editor_and_wait`, and calls `on_save`
+/// each time the target file is saved, until the editor is closed.
+fn start_editor_and_watch(files: &TempEditingFiles, on_save: impl Fn()) {
+    let (sender, receiver) = mpsc::channel();
+
+    // The folder is watched rather than the file,
+    // since some editors save by replacing the file with a new one.
+    let watcher = notify::recommended_watcher(sender).and_then(|mut watcher| {
+        watcher
+            .watch(files.dir_path(), RecursiveMode::NonRecursive)
+            .map(|()| watcher)
+    });
+
+    let _watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            println!(
+                "{}",
+                format!("Couldn't watch the target file, it's validated once the editor is closed: {err}")
+                    .yellow()
+            );
+            return start_editor_and_wait(files);
+        }
+    };
+
+    let mut child = spawn_editor(files);
+    let mut is_saved = false;
+
+    // A save can trigger several events: the file is validated once they stop.
+    while child
+        .try_wait()
+        .expect("An error occured waiting for the editor process.")
+        .is_none()
+    {
+        match receiver.recv_timeout(Duration::from_millis(200)) {
+            Ok(Ok(Event { kind, paths, .. })) => {
+                is_saved |= matches!(kind, EventKind::Create(_) | EventKind::Modify(_))
+                    && paths.contains(&files.target);
+            }
+            Ok(Err(_)) => (),
+            Err(RecvTimeoutError::Timeout) if is_saved => {
+                is_saved = false;
+                on_save();
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => {
+                child
+                    .wait()
+                    .expect("An error occured waiting for the editor process.");
+                break;
+            }
+        }
+    }
 }
@@ -0,0 +1,355 @@
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::display::display_results;
+use crate::escaping::{escape_path, serde_path};
+use crate::filesystem::{self, Outcome, RenamingsResults};
+use crate::history;
+use crate::interaction::{ask_user_to_recover, RecoveryAction};
+use crate::planning::Step;
+
+/// Write-ahead journal of the batch being applied,
+/// each entry is synced to the disk before going on.
+/// The journal file is locked as long as the batch is being applied.
+pub struct Journal {
+    file: File,
+    path: PathBuf,
+}
+
+/// Line of the journal
+#[derive(Serialize, Deserialize)]
+pub enum Entry {
+    /// Batch started by the process `pid` in `cwd`, with all its planned steps
+    Start {
+        pid: u32,
+        #[serde(with = "serde_path")]
+        cwd: PathBuf,
+        steps: Vec<Step>,
+    },
+
+    /// The nth step is about to be applied
+    Begin(usize),
+
+    /// The nth step is about to be reverted
+    Revert(usize),
+
+    /// The last step begun or reverted succeeded
+    Done,
+
+    /// The last step begun or reverted failed
+    Failed,
+}
+
+impl Journal {
+    /// Creates the journal of a new batch, unless an unfinished one already exists.
+    pub fn start(steps: &[Step]) -> Result<Journal, String> {
+        let path = config::get_path_to_journal()?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| format!("Couldn't create the directory {:?}: {}", parent, err))?;
+        }
+
+        let file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file
+                .lock()
+                .map(|()| file)
+                .map_err(|err| format!("Couldn't lock the journal {:?}: {}", path, err))?,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(format!(
+                    "An unfinished batch of renamings is in the journal {:?}, run diffren again to recover it.",
+                    path
+                ))
+            }
+            Err(err) => return Err(format!("Couldn't create the journal {:?}: {}", path, err)),
+        };
+
+        let cwd = std::env::current_dir()
+            .map_err(|err| format!("Couldn't get the current directory: {}", err))?;
+
+        let mut journal = Journal { file, path };
+
+        journal
+            .write(&Entry::Start {
+                pid: std::process::id(),
+                cwd,
+                steps: steps.to_vec(),
+            })
+            .map_err(|err| format!("Couldn't write the journal: {}", err))?;
+
+        Ok(journal)
+    }
+
+    /// Opens an existing journal to append entries to it, unless the process
+    /// that started its batch is still running it and holds its lock.
+    fn resume(path: &Path) -> Result<Option<Journal>, String> {
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|err| format!("Couldn't open the journal {:?}: {}", path, err))?;
+
+        match file.try_lock() {
+            Ok(()) => Ok(Some(Journal {
+                file,
+                path: path.to_path_buf(),
+            })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(err)) => {
+                Err(format!("Couldn't lock the journal {:?}: {}", path, err))
+            }
+        }
+    }
+
+    /// Appends an entry and waits for it to be on the disk.
+    pub fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()
+    }
+
+    /// Appends the outcome of the last step begun or reverted.
+    /// An error here isn't fatal: the recovery checks the paths
+    /// of a step without outcome.
+    pub fn write_outcome<T>(&mut self, result: &io::Result<T>) {
+        let entry = match result {
+            Ok(_) => Entry::Done,
+            Err(_) => Entry::Failed,
+        };
+
+        self.write(&entry).ok();
+    }
+
+    /// Deletes the journal once the batch is over.
+    pub fn close(self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            println!(
+                "{}",
+                format!("Couldn't delete the journal {:?}: {}", self.path, err).red()
+            );
+        }
+    }
+}
+
+/// State of an unfinished batch read from the journal
+struct UnfinishedBatch {
+    cwd: PathBuf,
+    steps: Vec<Step>,
+
+    /// Whether each step is currently applied
+    applied: Vec<bool>,
+
+    /// Step being applied or reverted when the process stopped,
+    /// and whether the file system shows it's done
+    interrupted: Option<(Step, bool)>,
+}
+
+/// Reads the journal, and returns the state of the batch it describes.
+fn read_journal(path: &Path) -> Result<UnfinishedBatch, String> {
+    let file =
+        File::open(path).map_err(|err| format!("Couldn't read the journal {:?}: {}", path, err))?;
+
+    // A line cut by a crash can't be parsed, and is the last one anyway.
+    let mut entries = BufReader::new(file)
+        .lines()
+        .map_while(|line| line.ok())
+        .map_while(|line| serde_json::from_str::<Entry>(&line).ok());
+
+    let (cwd, steps) = match entries.next() {
+        Some(Entry::Start { cwd, steps, .. }) => (cwd, steps),
+        _ => return Err(format!("The journal {:?} is invalid.", path)),
+    };
+
+    let mut applied = vec![false; steps.len()];
+    let mut ongoing = None;
+
+    for entry in entries {
+        match entry {
+            Entry::Begin(index) | Entry::Revert(index) if index >= steps.len() => {
+                return Err(format!("The journal {:?} is invalid.", path))
+            }
+            Entry::Begin(_) | Entry::Revert(_) => ongoing = Some(entry),
+            Entry::Done => match ongoing.take() {
+                Some(Entry::Begin(index)) => applied[index] = true,
+                Some(Entry::Revert(index)) => applied[index] = false,
+                _ => (),
+            },
+            Entry::Failed => ongoing = None,
+            Entry::Start { .. } => return Err(format!("The journal {:?} is invalid.", path)),
+        }
+    }
+
+    // The process stopped during a step: check where the path is now.
+    let interrupted = match ongoing {
+        Some(Entry::Begin(index)) => {
+            let step = steps[index].with_base(&cwd);
+            applied[index] = step.is_applied();
+            Some((step, applied[index]))
+        }
+        Some(Entry::Revert(index)) => match steps[index].reverse() {
+            Some(reverse) => {
+                let reverse = reverse.with_base(&cwd);
+                applied[index] = !reverse.is_applied();
+                Some((reverse, !applied[index]))
+            }
+            None => {
+                applied[index] = true;
+                None
+            }
+        },
+        _ => None,
+    };
+
+    Ok(UnfinishedBatch {
+        cwd,
+        steps,
+        applied,
+        interrupted,
+    })
+}
+
+/// Looks for a batch left unfinished by a previous run,
+/// and asks the user whether to finish it or roll it back.
+pub fn recover_unfinished_batch() -> Result<(), String> {
+    let path = config::get_path_to_journal()?;
+
+    if !path.exists() {
+        return Ok(());
+    }
+
+    // The lock is free once the process that started the batch is gone.
+    let Some(mut journal) = Journal::resume(&path)? else {
+        return Ok(());
+    };
+
+    let batch = read_journal(&path)?;
+
+    let applied_count = batch.applied.iter().filter(|applied| **applied).count();
+
+    println!(
+        "{}",
+        format!(
+            "A batch of renamings in {} was interrupted: {applied_count} of {} steps were applied.",
+            escape_path(&batch.cwd),
+            batch.steps.len()
+        )
+        .bold()
+        .red()
+    );
+
+    let mut results = vec![];
+
+    // How each step is applied once the batch is finished, to add it to the history.
+    let mut outcomes: Vec<Option<Outcome>> = batch
+        .applied
+        .iter()
+        .map(|applied| applied.then_some(Outcome::Done))
+        .collect();
+
+    let action = ask_user_to_recover();
+
+    // The step that was interrupted is finished or cleaned up before going on.
+    if let (Some((step, is_applied)), RecoveryAction::Finish | RecoveryAction::Rollback) =
+        (&batch.interrupted, &action)
+    {
+        filesystem::clean_interrupted_step(step, *is_applied)
+            .map_err(|err| format!("Couldn't clean up the interrupted step: {err}"))?;
+    }
+
+    match action {
+        RecoveryAction::Finish => {
+            for (index, step) in batch.steps.iter().enumerate() {
+                if batch.applied[index] {
+                    continue;
+                }
+
+                let step = step.with_base(&batch.cwd);
+
+                let result = journal
+                    .write(&Entry::Begin(index))
+                    .and_then(|()| filesystem::apply_step(&step));
+
+                journal.write_outcome(&result);
+                outcomes[index] = result.as_ref().ok().copied();
+                results.push((step, result));
+            }
+        }
+        RecoveryAction::Rollback => {
+            for (index, step) in batch.steps.iter().enumerate().rev() {
+                if !batch.applied[index] {
+                    continue;
+                }
+
+                let step = step.with_base(&batch.cwd);
+
+                let (step, result) = match step.reverse() {
+                    Some(reverse) => {
+                        let result = journal
+                            .write(&Entry::Revert(index))
+                            .and_then(|()| filesystem::apply_step(&reverse));
+
+                        journal.write_outcome(&result);
+                        (reverse, result)
+                    }
+                    None => (step, Err(filesystem::get_irreversible_error())),
+                };
+
+                results.push((step, result));
+            }
+        }
+        RecoveryAction::Ignore => return Ok(()),
+    }
+
+    let results = RenamingsResults {
+        results,
+        rollback: None,
+        pruned: vec![],
+    };
+
+    display_results(&results);
+
+    if results.results.iter().all(|(_, result)| result.is_ok()) {
+        journal.close();
+
+        // A rolled back batch left the paths where they were.
+        if let RecoveryAction::Finish = action {
+            if batch.applied.contains(&false) {
+                record_finished_batch(&batch, &outcomes)?;
+            }
+        }
+    } else {
+        println!(
+            "{}",
+            "Some renamings failed, the batch is kept in the journal.".red()
+        );
+    }
+
+    Ok(())
+}
+
+/// Adds a batch finished after its recovery to the history,
+/// with the outcome of each step applied.
+fn record_finished_batch(
+    batch: &UnfinishedBatch,
+    outcomes: &[Option<Outcome>],
+) -> Result<(), String> {
+    let results = batch
+        .steps
+        .iter()
+        .zip(outcomes)
+        .filter_map(|(step, outcome)| {
+            outcome.map(|outcome| (step.with_base(&batch.cwd), Ok(outcome)))
+        })
+        .collect();
+
+    history::record_batch(&RenamingsResults {
+        results,
+        rollback: None,
+        pruned: vec![],
+    })
+}
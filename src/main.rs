@@ -1,12 +1,15 @@
 use clap::CommandFactory;
 use clap::Parser;
-use cli::{Args, Subcommand};
+use cli::{Args, LnArgs, RunArgs, Subcommand};
 use colored::Colorize;
-use display::display_results;
-use filesystem::{apply_renamings, init_temporary_files, unwrap_paths_patterns};
+use display::{display_plan, display_results};
+use filesystem::{
+    apply_renamings, init_temporary_files, unwrap_paths_patterns, BufferFormat, STDIN_PATH,
+};
 use interaction::{ask_user_for_changes, NextAction};
+use planning::{describe_plan, plan_changes, Operation};
 use std::process::ExitCode;
-use validation::Renaming;
+use validation::ValidationRules;
 
 /// Display tables
 mod display;
@@ -20,12 +23,30 @@ mod interaction;
 /// Validation of renamings
 mod validation;
 
+/// Ordering of renamings
+mod planning;
+
 /// Configuration
 mod config;
 
 /// CLI
 mod cli;
 
+/// History of the renamings
+mod history;
+
+/// Journal of the batch being applied
+mod journal;
+
+/// Trash can
+mod trash;
+
+/// Git integration
+mod git;
+
+/// Escaping of the paths that aren't valid UTF-8
+mod escaping;
+
 fn main() -> ExitCode {
     match exec() {
         Ok(()) => ExitCode::SUCCESS,
@@ -41,11 +62,30 @@ fn exec() -> Result<(), String> {
     let mut cmd = Args::command();
 
     if let Some(command) = &args.command {
+        journal::recover_unfinished_batch()?;
+
         match command {
-            Subcommand::Run { paths } => run_renaming(paths.to_owned()),
+            Subcommand::Run(args) => run_renaming(args, Operation::Move),
+            Subcommand::Cp(args) => run_renaming(args, Operation::Copy),
+            Subcommand::Ln(LnArgs {
+                run, hard: true, ..
+            }) => run_renaming(run, Operation::HardLink),
+            Subcommand::Ln(LnArgs { run, absolute, .. }) => run_renaming(
+                run,
+                Operation::Symlink {
+                    absolute: *absolute,
+                },
+            ),
             Subcommand::GetConfig => config::print_config(),
+            Subcommand::History => history::print_history(),
+            Subcommand::Undo { number } => history::undo_batch(*number),
+            Subcommand::SetOnConflict { policy } => config::set_conflict_policy(policy),
             Subcommand::SetCustomEditor { command } => config::set_custom_editor_command(command),
-            Subcommand::UseEditor { text_editor } => config::set_editor_to_use(text_editor),
+            Subcommand::UseEditor {
+                text_editor: Some(text_editor),
+                detect: false,
+            } => config::set_editor_to_use(text_editor),
+            Subcommand::UseEditor { .. } => config::print_detected_editors(),
         }
     } else {
         cmd.print_long_help().unwrap();
@@ -53,28 +93,88 @@ fn exec() -> Result<(), String> {
     }
 }
 
-fn run_renaming(paths: Vec<String>) -> Result<(), String> {
+fn run_renaming(args: &RunArgs, operation: Operation) -> Result<(), String> {
+    if args.allow_delete && operation != Operation::Move {
+        return Err("Paths can only be deleted when they're renamed.".to_string());
+    }
+
     // Verify that an editor to use is defined
     if let Err(err) = config::is_there_an_editor_to_use() {
         return Err(err);
     }
 
-    // Default to "*" all files in the current directory
-    let paths = if paths.len() == 0 {
-        vec!["*".to_string()]
-    } else {
-        paths
+    let mut paths = args.paths.to_owned();
+
+    if (args.listing.stdin || args.listing.null) && !paths.iter().any(|path| path == STDIN_PATH) {
+        paths.push(STDIN_PATH.into());
+    }
+
+    // Default to "*" all files in the current directory,
+    // or to the current directory itself when listing recursively
+    if paths.is_empty() && args.listing.recursive {
+        paths.push(".".into());
+    } else if paths.is_empty() {
+        paths.push("*".into());
+    }
+
+    let paths = unwrap_paths_patterns(paths, &args.listing);
+
+    let format = BufferFormat {
+        layout: config::get_buffer_layout(),
+        line_ids: args.ids,
+        allow_delete: args.allow_delete,
     };
 
-    let paths = unwrap_paths_patterns(paths);
+    let rules = ValidationRules {
+        allow_delete: args.allow_delete,
+        keep_sources: operation != Operation::Move,
+        symlinks: matches!(operation, Operation::Symlink { .. }),
+        hard_links: operation == Operation::HardLink,
+        git: operation == Operation::Move && git::is_in_work_tree(),
+        check_uncommitted: args.check_uncommitted,
+        on_conflict: args.on_conflict.unwrap_or_else(config::get_conflict_policy),
+    };
 
-    let temp = init_temporary_files(paths.join("\n"));
+    let live = args.live && !config::is_editor_in_terminal();
+
+    if args.live && !live {
+        println!(
+            "{}",
+            "The editor runs in the terminal, the target names are only validated once it's closed."
+                .yellow()
+        );
+    }
+
+    let temp = init_temporary_files(&paths, format, rules.on_conflict);
+    let mut previous_renamings = vec![];
 
     loop {
-        match ask_user_for_changes(&temp) {
-            NextAction::Confirm(renamings) => {
-                let results = apply_renamings(renamings);
+        match ask_user_for_changes(&temp, &rules, operation, live, &mut previous_renamings) {
+            NextAction::Confirm(changes) => {
+                let plan = plan_changes(changes, operation, args.permanent)
+                    .map_err(|err| format!("Couldn't plan the changes: {err}"))?;
+
+                if args.dry_run {
+                    display_plan(&describe_plan(&plan));
+                    break;
+                }
+
+                let mut results = apply_renamings(plan, args.atomic)?;
+
+                // Only after a successful batch, not one partly failed or rolled back.
+                let is_successful = results.rollback.is_none()
+                    && results.results.iter().all(|(_, result)| result.is_ok());
+
+                if args.prune_empty && is_successful {
+                    filesystem::prune_empty_folders(&mut results);
+                }
+
                 display_results(&results);
+
+                if let Err(err) = history::record_batch(&results) {
+                    println!("{}", err.red());
+                }
+
                 break;
             }
             NextAction::Edit => continue,
@@ -0,0 +1,604 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::escaping::{serde_path, serde_renaming};
+use crate::git;
+use crate::trash;
+use crate::validation::{Change, Renaming};
+
+/// Operation applied to the file system, one at a time
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Step {
+    /// Moves a path
+    Rename(#[serde(with = "serde_renaming")] Renaming),
+
+    /// Moves a path tracked by git, and its entries in the index like `git mv`
+    GitRename(#[serde(with = "serde_renaming")] Renaming),
+
+    /// Moves a path to the trash, as `(path, path in the trash)`
+    Trash(#[serde(with = "serde_renaming")] Renaming),
+
+    /// Moves a path back from the trash, as `(path in the trash, path)`
+    Restore(#[serde(with = "serde_renaming")] Renaming),
+
+    /// Deletes a path for good
+    Delete(#[serde(with = "serde_path")] PathBuf),
+
+    /// Copies a path, with the content of a folder
+    Copy(#[serde(with = "serde_renaming")] Renaming),
+
+    /// Creates a symbolic link, as `(path the link points to, link path)`
+    Symlink(#[serde(with = "serde_renaming")] Renaming),
+
+    /// Creates a hard link, as `(existing path, link path)`
+    HardLink(#[serde(with = "serde_renaming")] Renaming),
+}
+
+/// How the current paths are turned into the target paths
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Renames the paths
+    Move,
+
+    /// Copies the paths, keeping their permissions and modification times
+    Copy,
+
+    /// Creates symbolic links to the paths,
+    /// relative to the link's folder unless `absolute`
+    Symlink { absolute: bool },
+
+    /// Creates hard links to the paths
+    HardLink,
+}
+
+impl Operation {
+    /// Verb describing the operation to the user
+    pub fn verb(self) -> &'static str {
+        match self {
+            Operation::Move => "rename",
+            Operation::Copy => "copy",
+            Operation::Symlink { .. } | Operation::HardLink => "link",
+        }
+    }
+
+    /// Returns the step creating the target path from the current one,
+    /// for an operation keeping the current path.
+    fn get_step(self, (current, target): Renaming) -> io::Result<Step> {
+        match self {
+            Operation::Move => Ok(Step::Rename((current, target))),
+            Operation::Copy => Ok(Step::Copy((current, target))),
+            Operation::Symlink { absolute } => {
+                let content = get_link_content(&current, &target, absolute)?;
+                Ok(Step::Symlink((content, target)))
+            }
+            Operation::HardLink => Ok(Step::HardLink((current, target))),
+        }
+    }
+}
+
+impl Step {
+    /// Returns the step undoing this one, if it can be undone.
+    pub fn reverse(&self) -> Option<Step> {
+        match self {
+            Step::Rename((from, to)) => Some(Step::Rename((to.clone(), from.clone()))),
+            Step::GitRename((from, to)) => Some(Step::GitRename((to.clone(), from.clone()))),
+            Step::Trash((from, to)) => Some(Step::Restore((to.clone(), from.clone()))),
+            Step::Restore((from, to)) => Some(Step::Trash((to.clone(), from.clone()))),
+            Step::Delete(_) => None,
+            Step::Copy((_, to)) | Step::Symlink((_, to)) | Step::HardLink((_, to)) => {
+                Some(Step::Delete(to.clone()))
+            }
+        }
+    }
+
+    /// Returns the path moved and where it's moved to,
+    /// unless the step doesn't move a path.
+    pub fn get_move(&self) -> Option<&Renaming> {
+        match self {
+            Step::Rename(renaming)
+            | Step::GitRename(renaming)
+            | Step::Trash(renaming)
+            | Step::Restore(renaming) => Some(renaming),
+            Step::Delete(_) | Step::Copy(_) | Step::Symlink(_) | Step::HardLink(_) => None,
+        }
+    }
+
+    /// Returns the same step with its relative paths based on another directory.
+    pub fn with_base(&self, base: &Path) -> Step {
+        let join = |(from, to): &Renaming| (base.join(from), base.join(to));
+
+        match self {
+            Step::Rename(renaming) => Step::Rename(join(renaming)),
+            Step::GitRename(renaming) => Step::GitRename(join(renaming)),
+            Step::Trash(renaming) => Step::Trash(join(renaming)),
+            Step::Restore(renaming) => Step::Restore(join(renaming)),
+            Step::Delete(path) => Step::Delete(base.join(path)),
+            Step::Copy(renaming) => Step::Copy(join(renaming)),
+            // A relative link is relative to its own folder, not to the base.
+            Step::Symlink((content, link)) => Step::Symlink((content.clone(), base.join(link))),
+            Step::HardLink(renaming) => Step::HardLink(join(renaming)),
+        }
+    }
+
+    /// Whether the file system shows that this step has been applied.
+    pub fn is_applied(&self) -> bool {
+        match self {
+            Step::Delete(path) => path.symlink_metadata().is_err(),
+            Step::Copy((_, to)) | Step::Symlink((_, to)) | Step::HardLink((_, to)) => {
+                to.symlink_metadata().is_ok()
+            }
+            // A move across file systems can stop once its copy is in place,
+            // before its source is deleted: the target only exists once it's complete.
+            step => {
+                let (_, to) = step.get_move().unwrap();
+                to.symlink_metadata().is_ok()
+            }
+        }
+    }
+}
+
+/// Step of a plan, as described to the user
+pub enum PlannedStep {
+    /// Folder created before a renaming into it
+    CreateFolder(PathBuf),
+
+    /// Renaming from a current path to a target path
+    Rename(Renaming),
+
+    /// Renaming to a temporary name, to break a cycle
+    ToTemporary(Renaming),
+
+    /// Renaming from a temporary name to the target path
+    FromTemporary(Renaming),
+
+    /// Moving a path to the trash
+    Trash(Renaming),
+
+    /// Moving a path back from the trash
+    Restore(Renaming),
+
+    /// Deleting a path for good
+    Delete(PathBuf),
+
+    /// Copying a path
+    Copy(Renaming),
+
+    /// Creating a symbolic link, as `(path the link points to, link path)`
+    Symlink(Renaming),
+
+    /// Creating a hard link
+    HardLink(Renaming),
+}
+
+/// Turns the changes into the steps to apply:
+/// the deleted and overwritten paths are moved out of the way first (to the trash,
+/// or to a temporary name if they're deleted for good at the end),
+/// then the existing targets to back up, and then the renamings are applied in order.
+/// The current paths of a copy or a link stay where they are, so these need no ordering.
+pub fn plan_changes(
+    changes: Vec<Change>,
+    operation: Operation,
+    permanent: bool,
+) -> io::Result<Vec<Step>> {
+    let mut renamings = vec![];
+    let mut deletions = vec![];
+    let mut overwritten = vec![];
+    let mut backups = vec![];
+
+    for change in changes {
+        match change {
+            Change::Rename(renaming) | Change::Numbered(renaming) => renamings.push(renaming),
+            Change::Delete(path) => deletions.push(path),
+            Change::Overwrite(renaming) => {
+                overwritten.push(renaming.1.clone());
+                renamings.push(renaming);
+            }
+            Change::Backup(renaming, backup) => {
+                backups.push((renaming.1.clone(), backup));
+                renamings.push(renaming);
+            }
+        }
+    }
+
+    let mut taken: HashSet<PathBuf> = renamings
+        .iter()
+        .chain(&backups)
+        .flat_map(|(current, target)| [current.clone(), target.clone()])
+        .chain(deletions.iter().cloned())
+        .collect();
+
+    // The replaced targets go to the trash too, unless the deletions are permanent.
+    deletions.extend(overwritten);
+
+    let (trashed, deleted) = if permanent {
+        (vec![], deletions)
+    } else {
+        (deletions, vec![])
+    };
+
+    let mut steps = vec![];
+    let mut final_steps = vec![];
+
+    for path in trashed {
+        let path_in_trash = trash::get_path_in_trash(&path, &taken)?;
+        taken.insert(path_in_trash.clone());
+
+        move_sources(&mut renamings, &path, &path_in_trash);
+        steps.push(Step::Trash((path, path_in_trash)));
+    }
+
+    for path in deleted {
+        let temporary = get_temporary_path(&path, &taken);
+        taken.insert(temporary.clone());
+
+        move_sources(&mut renamings, &path, &temporary);
+        steps.push(Step::Rename((path, temporary.clone())));
+        final_steps.push(Step::Delete(temporary));
+    }
+
+    for (path, backup) in backups {
+        move_sources(&mut renamings, &path, &backup);
+        steps.push(Step::Rename((path, backup)));
+    }
+
+    if operation == Operation::Move {
+        let renamings = plan_renamings(renamings, &mut taken);
+
+        if git::is_in_work_tree() {
+            steps.extend(use_git_for_tracked_paths(renamings));
+        } else {
+            steps.extend(renamings.into_iter().map(Step::Rename));
+        }
+    } else {
+        // Copying or linking a folder already creates its content at the matching place.
+        let is_created_with_folder = |(path, target): &Renaming| {
+            renamings.iter().any(|(folder, folder_target)| {
+                folder != path
+                    && get_moved_path(path, folder, folder_target).as_ref() == Some(target)
+            })
+        };
+
+        for renaming in &renamings {
+            if !is_created_with_folder(renaming) {
+                steps.push(operation.get_step(renaming.clone())?);
+            }
+        }
+    }
+
+    steps.extend(final_steps);
+
+    Ok(steps)
+}
+
+/// Orders the renamings so that each target is free when it's renamed to.
+/// Chains (a→b, b→c) are applied from their end, and cycles (a→b, b→a)
+/// are broken by first moving one of their paths to a temporary name.
+/// A target inside a folder that's still to be renamed waits for it, and
+/// the paths inside a renamed folder are then renamed from their new place.
+fn plan_renamings(renamings: Vec<Renaming>, taken: &mut HashSet<PathBuf>) -> Vec<Renaming> {
+    let mut pending = renamings;
+    let mut plan = Vec::with_capacity(pending.len());
+
+    while !pending.is_empty() {
+        let ready = (0..pending.len()).find(|&index| get_blocking_index(&pending, index).is_none());
+
+        let (current, target) = match ready {
+            Some(index) => pending.remove(index),
+
+            // All the remaining targets are still occupied, so there's a cycle:
+            // it's found by following the renamings blocking each other.
+            None => {
+                let mut visited = vec![false; pending.len()];
+                let mut index = 0;
+
+                while !visited[index] {
+                    visited[index] = true;
+                    index =
+                        get_blocking_index(&pending, index).expect("All the renamings are blocked");
+                }
+
+                let current = pending[index].0.clone();
+                let temporary = get_temporary_path(&current, taken);
+                taken.insert(temporary.clone());
+
+                (current, temporary)
+            }
+        };
+
+        move_sources(&mut pending, &current, &target);
+        plan.push((current, target));
+    }
+
+    plan
+}
+
+/// Updates the current paths of the renamings that are inside a moved folder.
+/// The ones already at their target, like a child moved along with its folder, are done.
+fn move_sources(renamings: &mut Vec<Renaming>, from: &Path, to: &Path) {
+    for (current, _) in renamings.iter_mut() {
+        if let Some(moved) = get_moved_path(current, from, to) {
+            *current = moved;
+        }
+    }
+
+    renamings.retain(|(current, target)| current != target);
+}
+
+/// Returns the index of another pending renaming whose current path is,
+/// or contains, the target of the given one.
+fn get_blocking_index(pending: &[Renaming], index: usize) -> Option<usize> {
+    let target = &pending[index].1;
+
+    (0..pending.len()).find(|&other| other != index && target.starts_with(&pending[other].0))
+}
+
+/// Returns where a path is once `from` is moved to `to`,
+/// if it's `from` itself or inside it.
+pub fn get_moved_path(path: &Path, from: &Path, to: &Path) -> Option<PathBuf> {
+    match path.strip_prefix(from) {
+        Ok(rest) if rest.as_os_str().is_empty() => Some(to.to_path_buf()),
+        Ok(rest) => Some(to.join(rest)),
+        Err(_) => None,
+    }
+}
+
+/// Renames the paths tracked by git with their index entries,
+/// following them through their temporary names and moved folders.
+fn use_git_for_tracked_paths(renamings: Vec<Renaming>) -> Vec<Step> {
+    let work_tree = git::WorkTree::read(Path::new("."), &[], false);
+    let mut moves: Vec<Renaming> = vec![];
+
+    renamings
+        .into_iter()
+        .map(|(current, target)| {
+            // Git only knows the path from before the previous steps.
+            let original = moves
+                .iter()
+                .rev()
+                .fold(current.clone(), |path, (from, to)| {
+                    get_moved_path(&path, to, from).unwrap_or(path)
+                });
+
+            moves.push((current.clone(), target.clone()));
+
+            let is_tracked = work_tree
+                .as_ref()
+                .is_some_and(|work_tree| work_tree.is_tracked(&original));
+
+            if is_tracked {
+                Step::GitRename((current, target))
+            } else {
+                Step::Rename((current, target))
+            }
+        })
+        .collect()
+}
+
+/// Returns an unused path next to the given one, like `dir/.name.diffren-0`.
+fn get_temporary_path(path: &Path, taken: &HashSet<PathBuf>) -> PathBuf {
+    (0..)
+        .map(|n| get_hidden_sibling(path, &n.to_string()))
+        .find(|candidate| !taken.contains(candidate) && !candidate.exists())
+        .expect("Couldn't find a free temporary name")
+}
+
+/// Returns where a path is copied before being renamed to its target, like `dir/.name.diffren-copy`,
+/// so that a copy interrupted by a crash is found from its target.
+pub fn get_partial_copy_path(target: &Path) -> PathBuf {
+    get_hidden_sibling(target, "copy")
+}
+
+/// Returns the hidden path next to the given one, like `dir/.name.diffren-<suffix>`.
+fn get_hidden_sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".diffren-{suffix}"));
+    path.with_file_name(name)
+}
+
+/// Returns what a symbolic link at `link` must contain to point to `path`.
+fn get_link_content(path: &Path, link: &Path, absolute: bool) -> io::Result<PathBuf> {
+    let cwd = std::env::current_dir()?;
+    let path = normalize_path(&cwd.join(path));
+
+    if absolute {
+        return Ok(path);
+    }
+
+    let link = normalize_path(&cwd.join(link));
+    let link_folder = link.parent().unwrap_or(Path::new("/"));
+
+    let common = path
+        .components()
+        .zip(link_folder.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut content: PathBuf = link_folder
+        .components()
+        .skip(common)
+        .map(|_| Component::ParentDir)
+        .collect();
+
+    content.extend(path.components().skip(common));
+
+    Ok(content)
+}
+
+/// Removes the `.` and `..` components of an absolute path, without resolving the links.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+/// Describes what applying the plan will do, including the folders
+/// created for the targets and the temporary names.
+pub fn describe_plan(plan: &[Step]) -> Vec<PlannedStep> {
+    let mut steps = vec![];
+    let mut created_folders: HashSet<PathBuf> = HashSet::new();
+    let mut temporaries: HashSet<&PathBuf> = HashSet::new();
+
+    for (index, step) in plan.iter().enumerate() {
+        let (current, target) = match step {
+            Step::Rename(renaming)
+            | Step::GitRename(renaming)
+            | Step::Copy(renaming)
+            | Step::Symlink(renaming)
+            | Step::HardLink(renaming) => renaming,
+            Step::Trash(renaming) => {
+                steps.push(PlannedStep::Trash(renaming.clone()));
+                continue;
+            }
+            Step::Restore(renaming) => {
+                steps.push(PlannedStep::Restore(renaming.clone()));
+                continue;
+            }
+            Step::Delete(path) => {
+                steps.push(PlannedStep::Delete(path.clone()));
+                continue;
+            }
+        };
+
+        let missing_folders: Vec<&Path> = target
+            .ancestors()
+            .skip(1)
+            .filter(|folder| !folder.as_os_str().is_empty())
+            .take_while(|folder| !folder.exists() && !created_folders.contains(*folder))
+            .collect();
+
+        for folder in missing_folders.into_iter().rev() {
+            created_folders.insert(folder.to_path_buf());
+            steps.push(PlannedStep::CreateFolder(folder.to_path_buf()));
+        }
+
+        // A renamed folder holds the targets of the next steps.
+        created_folders.insert(target.clone());
+
+        let renaming = (current.clone(), target.clone());
+
+        // A temporary path is moved again by a later step.
+        let is_moved_later = plan[index + 1..].iter().any(|later| match later {
+            Step::Delete(path) => path == target,
+            later => later.get_move().is_some_and(|(from, _)| from == target),
+        });
+
+        let planned_step = match step {
+            Step::Copy(_) => PlannedStep::Copy(renaming),
+            Step::Symlink(_) => PlannedStep::Symlink(renaming),
+            Step::HardLink(_) => PlannedStep::HardLink(renaming),
+            _ if is_moved_later => {
+                temporaries.insert(target);
+                PlannedStep::ToTemporary(renaming)
+            }
+            _ if temporaries.contains(current) => PlannedStep::FromTemporary(renaming),
+            _ => PlannedStep::Rename(renaming),
+        };
+
+        steps.push(planned_step);
+    }
+
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Plans renamings between paths of an empty folder, and returns the steps
+    /// with the paths relative to that folder.
+    fn plan(renamings: &[(&str, &str)]) -> Vec<(String, String)> {
+        let dir = TempDir::new().unwrap();
+        let relative = |path: &Path| path.strip_prefix(dir.path()).unwrap().display().to_string();
+
+        let renamings = renamings
+            .iter()
+            .map(|(current, target)| (dir.path().join(current), dir.path().join(target)))
+            .collect();
+
+        plan_renamings(renamings, &mut HashSet::new())
+            .iter()
+            .map(|(current, target)| (relative(current), relative(target)))
+            .collect()
+    }
+
+    /// Makes the expected steps from string literals.
+    fn steps(steps: &[(&str, &str)]) -> Vec<(String, String)> {
+        steps
+            .iter()
+            .map(|(current, target)| (current.to_string(), target.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn breaks_a_swap_with_a_temporary_name() {
+        assert_eq!(
+            plan(&[("a", "b"), ("b", "a")]),
+            steps(&[("a", ".a.diffren-0"), ("b", "a"), (".a.diffren-0", "b")])
+        );
+    }
+
+    #[test]
+    fn breaks_a_cycle_of_three_with_a_temporary_name() {
+        assert_eq!(
+            plan(&[("a", "b"), ("b", "c"), ("c", "a")]),
+            steps(&[
+                ("a", ".a.diffren-0"),
+                ("c", "a"),
+                ("b", "c"),
+                (".a.diffren-0", "b")
+            ])
+        );
+    }
+
+    #[test]
+    fn applies_a_shift_from_its_end() {
+        assert_eq!(
+            plan(&[("01", "02"), ("02", "03"), ("03", "04")]),
+            steps(&[("03", "04"), ("02", "03"), ("01", "02")])
+        );
+    }
+
+    #[test]
+    fn moves_a_child_along_with_its_folder() {
+        assert_eq!(plan(&[("A", "B"), ("A/f", "B/f")]), steps(&[("A", "B")]));
+    }
+
+    #[test]
+    fn renames_a_child_from_the_new_place_of_its_folder() {
+        assert_eq!(
+            plan(&[("A", "B"), ("A/f", "B/g")]),
+            steps(&[("A", "B"), ("B/f", "B/g")])
+        );
+    }
+
+    #[test]
+    fn waits_for_a_folder_to_be_freed_before_renaming_into_its_place() {
+        assert_eq!(
+            plan(&[("A/f", "g"), ("A", "B"), ("x", "A")]),
+            steps(&[("A/f", "g"), ("A", "B"), ("x", "A")])
+        );
+    }
+
+    #[test]
+    fn breaks_a_cycle_across_folders() {
+        assert_eq!(
+            plan(&[("a", "b/x"), ("b", "a/y")]),
+            steps(&[("a", ".a.diffren-0"), ("b", "a/y"), (".a.diffren-0", "b/x")])
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use chrono::Local;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::filesystem::{self, Outcome};
+use crate::validation::Renaming;
+
+/// Returns the home trash directory, as defined by the freedesktop.org specification:
+/// `$XDG_DATA_HOME/Trash`, or `~/.local/share/Trash`.
+fn get_trash_dir() -> io::Result<PathBuf> {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")));
+
+    match data_home {
+        Some(data_home) => Ok(data_home.join("Trash")),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "couldn't find the trash directory",
+        )),
+    }
+}
+
+/// Whether a path is in the trash.
+pub fn is_in_trash(path: &Path) -> bool {
+    get_trash_dir()
+        .map(|trash| path.starts_with(trash.join("files")))
+        .unwrap_or(false)
+}
+
+/// Returns an unused path in the trash for the given path.
+pub fn get_path_in_trash(path: &Path, taken: &HashSet<PathBuf>) -> io::Result<PathBuf> {
+    let files = get_trash_dir()?.join("files");
+    let file_name = path.file_name().unwrap_or_default();
+
+    let trashed = (0..)
+        .map(|n| {
+            let mut name = OsString::from(file_name);
+
+            if n > 0 {
+                name.push(format!(".{n}"));
+            }
+
+            files.join(name)
+        })
+        .find(|candidate| {
+            !taken.contains(candidate) && !candidate.exists() && !get_info_path(candidate).exists()
+        })
+        .expect("Couldn't find a free name in the trash");
+
+    Ok(trashed)
+}
+
+/// Returns the path of the `.trashinfo` file describing a path in the trash.
+fn get_info_path(trashed: &Path) -> PathBuf {
+    let mut name = OsString::from(trashed.file_name().unwrap_or_default());
+    name.push(".trashinfo");
+
+    trashed
+        .parent()
+        .and_then(Path::parent)
+        .unwrap_or(Path::new(""))
+        .join("info")
+        .join(name)
+}
+
+/// Moves a path to the trash, as `(path, path in the trash)`,
+/// with its `.trashinfo` file written first.
+pub fn move_to_trash((path, trashed): &Renaming) -> io::Result<Outcome> {
+    let info_path = get_info_path(trashed);
+
+    if let Some(info_dir) = info_path.parent() {
+        fs::create_dir_all(info_dir)?;
+    }
+
+    let info = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        encode_path(&get_absolute_path(path)?),
+        Local::now().format("%Y-%m-%dT%H:%M:%S")
+    );
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&info_path)?;
+    file.write_all(info.as_bytes())?;
+
+    filesystem::rename(&(path.clone(), trashed.clone())).inspect_err(|_| {
+        fs::remove_file(&info_path).ok();
+    })
+}
+
+/// Moves a path back from the trash, as `(path in the trash, path)`,
+/// and deletes its `.trashinfo` file.
+pub fn restore_from_trash((trashed, path): &Renaming) -> io::Result<Outcome> {
+    let outcome = filesystem::rename(&(trashed.clone(), path.clone()))?;
+
+    match fs::remove_file(get_info_path(trashed)) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(outcome),
+    }
+}
+
+/// Returns the absolute path, without resolving the symbolic link it may be.
+fn get_absolute_path(path: &Path) -> io::Result<PathBuf> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+        _ => std::env::current_dir()?,
+    };
+
+    Ok(parent.join(path.file_name().unwrap_or_default()))
+}
+
+/// Percent-encodes a path for the `Path` key of a `.trashinfo` file.
+fn encode_path(path: &Path) -> String {
+    path.as_os_str()
+        .as_encoded_bytes()
+        .iter()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (*byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -1,10 +1,80 @@
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::escaping::unescape_path;
+use crate::filesystem::{BufferFormat, BufferLayout};
+use crate::git::WorkTree;
+use crate::planning::get_moved_path;
 
 /// Path renaming with current and target paths
 pub type Renaming = (PathBuf, PathBuf);
 
+/// A target line with only this word, or this word and a comment after a space,
+/// deletes its path when deletions are allowed
+pub const DELETE_PREFIX: &str = "!delete";
+
+/// Change requested on a path
+pub enum Change {
+    Rename(Renaming),
+    Delete(PathBuf),
+
+    /// Renaming replacing the existing target path
+    Overwrite(Renaming),
+
+    /// Renaming after the existing target path is moved to a backup path
+    Backup(Renaming, PathBuf),
+
+    /// Renaming to a numbered target, since the requested one exists
+    Numbered(Renaming),
+}
+
+/// What to do when a target path already exists
+#[derive(Copy, Clone, PartialEq, Eq, Default, clap::ValueEnum, Debug, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+    /// The renaming is refused
+    #[default]
+    Error,
+
+    /// The existing path is moved to the trash, or deleted for good with `--permanent`
+    Overwrite,
+
+    /// The existing path is renamed to `name~`, or `name.~N~` if there are already backups
+    Backup,
+
+    /// The target is numbered, like `name (1).ext`
+    Number,
+}
+
+/// What the validation allows
+#[derive(Clone, Copy, Default)]
+pub struct ValidationRules {
+    /// An empty target, from an emptied line or a `DELETE_PREFIX` one, deletes the current path
+    pub allow_delete: bool,
+
+    /// The current paths stay where they are (when copied or linked),
+    /// so their names can't be targets
+    pub keep_sources: bool,
+
+    /// The paths are linked with symbolic links, so a target can't be
+    /// inside a linked folder: it would be created in the original folder
+    pub symlinks: bool,
+
+    /// The paths are linked with hard links, which folders can't be
+    pub hard_links: bool,
+
+    /// The paths are in a git work tree, so a tracked path can't be moved to an ignored one
+    pub git: bool,
+
+    /// Paths with changes not committed in git can't be changed
+    pub check_uncommitted: bool,
+
+    /// What to do when a target already exists
+    pub on_conflict: ConflictPolicy,
+}
+
 /// Path renaming validation
-pub type Validation = Result<Renaming, ValidationError>;
+pub type Validation = Result<Change, ValidationError>;
 
 /// Renaming validation error,
 pub enum ValidationError {
@@ -13,22 +83,52 @@ pub enum ValidationError {
     SeveralTargetsAreTheSame(Renaming, u32),
     InvalidTargetPath(Renaming),
     InvalidCurrentPath(Renaming),
+    TargetInsideItself(Renaming),
+    TargetInsideLinkedFolder(Renaming),
+    FolderCantBeHardLinked(Renaming),
+    TargetIsIgnored(Renaming),
+    HasUncommittedChanges(Renaming),
     Unchanged(Renaming),
 }
 
 /// Verify that each renaming is possible, or add a detailed error.
 /// Also counts the non-blocking errors (only a unchanged path is counted as a non-blocking error)
 /// Returns a tuple containing the renamings, the changes count and then the errors count.
-pub fn validate_renamings(renamings: Vec<Renaming>) -> (Vec<Validation>, u32, u32) {
+pub fn validate_renamings(
+    renamings: Vec<Renaming>,
+    rules: &ValidationRules,
+) -> (Vec<Validation>, u32, u32) {
     let mut errors = 0;
     let mut changes = 0;
 
     let renamings_copy = renamings.clone();
 
+    // Git is asked about all the paths at once, in the work tree containing them.
+    let work_tree = if rules.git || rules.check_uncommitted {
+        let folder = renamings
+            .first()
+            .and_then(|(current, _)| current.parent())
+            .unwrap_or(Path::new("."));
+
+        let targets: Vec<&Path> = if rules.git {
+            renamings
+                .iter()
+                .map(|(_, target)| target.as_path())
+                .collect()
+        } else {
+            vec![]
+        };
+
+        WorkTree::read(folder, &targets, rules.check_uncommitted)
+    } else {
+        None
+    };
+
     let renamings: Vec<Validation> = renamings
         .into_iter()
         .map(|renaming| {
-            let validation = validate_renaming(renaming, &renamings_copy);
+            let validation =
+                validate_renaming(renaming, &renamings_copy, rules, work_tree.as_ref());
 
             match validation {
                 Ok(_) => changes += 1,
@@ -44,26 +144,153 @@ pub fn validate_renamings(renamings: Vec<Renaming>) -> (Vec<Validation>, u32, u3
 }
 
 /// Verify the validity of a renaming.
-fn validate_renaming(renaming: Renaming, renamings: &Vec<Renaming>) -> Validation {
+fn validate_renaming(
+    renaming: Renaming,
+    renamings: &Vec<Renaming>,
+    rules: &ValidationRules,
+    work_tree: Option<&WorkTree>,
+) -> Validation {
+    let git = |check: fn(&WorkTree, &Path) -> bool, path: &Path| {
+        work_tree.is_some_and(|work_tree| check(work_tree, path))
+    };
+
     let (current, target) = &renaming;
 
     if current.eq(target) {
         Err(ValidationError::Unchanged(renaming))
     } else if !current.exists() {
         Err(ValidationError::FileDoesntExist(renaming))
-    } else if current.to_str().unwrap_or("").is_empty() {
+    } else if current.as_os_str().is_empty() {
         Err(ValidationError::InvalidCurrentPath(renaming))
-    } else if target.exists() {
-        Err(ValidationError::TargetAlreadyExists(renaming))
-    } else if target.to_str().unwrap_or("").is_empty() {
+    } else if rules.check_uncommitted && git(WorkTree::has_uncommitted_changes, current) {
+        Err(ValidationError::HasUncommittedChanges(renaming))
+    } else if rules.allow_delete && target.as_os_str().is_empty() {
+        Ok(Change::Delete(renaming.0))
+    } else if target.as_os_str().is_empty() {
         Err(ValidationError::InvalidTargetPath(renaming))
+    } else if target.starts_with(current) {
+        Err(ValidationError::TargetInsideItself(renaming))
+    } else if rules.hard_links && current.is_dir() {
+        Err(ValidationError::FolderCantBeHardLinked(renaming))
+    } else if rules.symlinks && is_inside_linked_folder(&renaming, renamings) {
+        Err(ValidationError::TargetInsideLinkedFolder(renaming))
     } else if let Err(count) = validate_target_uniqueness(target, &renamings) {
         Err(ValidationError::SeveralTargetsAreTheSame(renaming, count))
+    } else if rules.git && git(WorkTree::is_ignored, target) && git(WorkTree::is_tracked, current) {
+        Err(ValidationError::TargetIsIgnored(renaming))
+    } else if target.exists() && (rules.keep_sources || !is_renamed_away(target, renamings)) {
+        resolve_conflict(renaming, renamings, rules.on_conflict)
+    } else {
+        Ok(Change::Rename(renaming))
+    }
+}
+
+/// Applies the conflict policy to a renaming whose target already exists.
+fn resolve_conflict(
+    renaming: Renaming,
+    renamings: &[Renaming],
+    policy: ConflictPolicy,
+) -> Validation {
+    let is_free = |path: &Path| {
+        path.symlink_metadata().is_err() && !renamings.iter().any(|(_, target)| target == path)
+    };
+
+    match policy {
+        ConflictPolicy::Error => Err(ValidationError::TargetAlreadyExists(renaming)),
+        ConflictPolicy::Overwrite => Ok(Change::Overwrite(renaming)),
+        ConflictPolicy::Backup => {
+            let backup = get_backup_path(&renaming.1, is_free);
+            Ok(Change::Backup(renaming, backup))
+        }
+        ConflictPolicy::Number => {
+            let numbered = get_numbered_path(&renaming.1, is_free);
+            Ok(Change::Numbered((renaming.0, numbered)))
+        }
+    }
+}
+
+/// Returns the backup path of an existing path, like GNU `mv --backup` does:
+/// `name~`, or `name.~N~` after the last numbered backup if there is one.
+fn get_backup_path(path: &Path, is_free: impl Fn(&Path) -> bool) -> PathBuf {
+    let with_suffix = |suffix: &str| {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(suffix);
+        path.with_file_name(name)
+    };
+
+    let last_number = (1..)
+        .take_while(|n| !is_free(&with_suffix(&format!(".~{n}~"))))
+        .last();
+
+    match last_number {
+        None if is_free(&with_suffix("~")) => with_suffix("~"),
+        last_number => (last_number.unwrap_or(0) + 1..)
+            .map(|n| with_suffix(&format!(".~{n}~")))
+            .find(|backup| is_free(backup))
+            .expect("Couldn't find a free backup path"),
+    }
+}
+
+/// Returns the first free numbered path, like `name (1).ext`.
+/// The number is put before the extension of a file.
+fn get_numbered_path(path: &Path, is_free: impl Fn(&Path) -> bool) -> PathBuf {
+    let (stem, extension) = if path.is_dir() {
+        (path.file_name().unwrap_or_default(), None)
+    } else {
+        (path.file_stem().unwrap_or_default(), path.extension())
+    };
+
+    (1..)
+        .map(|n| {
+            let mut name = stem.to_os_string();
+            name.push(format!(" ({n})"));
+
+            if let Some(extension) = extension {
+                name.push(".");
+                name.push(extension);
+            }
+
+            path.with_file_name(name)
+        })
+        .find(|numbered| is_free(numbered))
+        .expect("Couldn't find a free numbered path")
+}
+
+/// Whether a buffer line is the word `DELETE_PREFIX`, alone or followed by whitespace.
+/// It's checked before unescaping, so a name like `!deleted scenes.mkv` isn't one.
+pub fn is_deletion_line(line: &str) -> bool {
+    line.strip_prefix(DELETE_PREFIX)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+/// Reads a target line, a deletion line giving an empty target.
+fn read_target_line(line: &str) -> Result<PathBuf, String> {
+    if is_deletion_line(line) {
+        Ok(PathBuf::new())
     } else {
-        Ok(renaming)
+        unescape_path(line)
     }
 }
 
+/// Whether a target is inside the link to another folder of the batch,
+/// unless it's the place the link already gives to its path.
+fn is_inside_linked_folder((path, target): &Renaming, renamings: &[Renaming]) -> bool {
+    renamings.iter().any(|(folder, link)| {
+        folder != path
+            && target.starts_with(link)
+            && folder.is_dir()
+            && get_moved_path(path, folder, link).as_ref() != Some(target)
+    })
+}
+
+/// Whether a path is, or is inside, the current path of another renaming in the batch,
+/// so it will be free by the time it's renamed to.
+fn is_renamed_away(path: &Path, renamings: &[Renaming]) -> bool {
+    renamings
+        .iter()
+        .any(|(current, target)| path.starts_with(current) && current != target)
+}
+
 fn validate_target_uniqueness(target: &PathBuf, renamings: &Vec<Renaming>) -> Result<(), u32> {
     let count = renamings
         .iter()
@@ -79,21 +306,161 @@ fn validate_target_uniqueness(target: &PathBuf, renamings: &Vec<Renaming>) -> Re
 
 /// "Zip" the two files (current and target) contents together,
 /// and fails if the two files don't have the same number of lines.
+/// With line IDs, the lines are matched by ID instead.
 pub fn combine_paths_vecs(
-    current: Vec<PathBuf>,
-    target: Vec<PathBuf>,
+    current: Vec<String>,
+    target: Vec<String>,
+    format: BufferFormat,
 ) -> Result<Vec<Renaming>, String> {
+    if format.line_ids {
+        return combine_paths_by_ids(current, target, format.allow_delete);
+    }
+
     if current.len() != target.len() {
-        return Err("The two files don't have the same number of lines.".to_string());
+        return Err(match format.layout {
+            BufferLayout::TwoFiles => "The two files don't have the same number of lines.",
+            BufferLayout::SingleFile => "Each current name must be followed by one target name.",
+        }
+        .to_string());
+    }
+
+    current
+        .iter()
+        .zip(&target)
+        .map(|(current, target)| Ok((unescape_path(current)?, read_target_line(target)?)))
+        .collect()
+}
+
+/// Matches the target lines with the current lines by their IDs.
+/// A current line whose ID isn't in the target lines is left unchanged,
+/// or deleted (with an empty target) when deletions are allowed.
+fn combine_paths_by_ids(
+    current: Vec<String>,
+    target: Vec<String>,
+    allow_delete: bool,
+) -> Result<Vec<Renaming>, String> {
+    let mut targets = HashMap::new();
+
+    // An emptied line is the same as a removed one.
+    for line in target.iter().filter(|line| !line.is_empty()) {
+        let (id, path) = split_line_id(line)?;
+
+        if targets.insert(id, read_target_line(path)?).is_some() {
+            return Err(format!("The ID {id} is on several target lines."));
+        }
+    }
+
+    let mut renamings = vec![];
+
+    for line in &current {
+        let (id, path) = split_line_id(line)?;
+        let path = unescape_path(path)?;
+
+        let target = match targets.remove(&id) {
+            Some(target) => target,
+            None if allow_delete => PathBuf::new(),
+            None => path.clone(),
+        };
+
+        renamings.push((path, target));
+    }
+
+    match targets.keys().min() {
+        Some(id) => Err(format!("The ID {id} doesn't match any current line.")),
+        None => Ok(renamings),
     }
+}
+
+/// Splits a line like `0042<TAB>path` into its ID and its path.
+/// A line with only an ID has an empty path, which is still escaped.
+pub fn split_line_id(line: &str) -> Result<(usize, &str), String> {
+    let (id, path) = match line.split_once('\t') {
+        Some((id, path)) => (id, path),
+        None if line.trim().parse::<usize>().is_ok() => (line, ""),
+        None => {
+            return Err(format!(
+                "The line `{line}` doesn't start with an ID and a tab."
+            ))
+        }
+    };
+
+    let id = id
+        .trim()
+        .parse()
+        .map_err(|_| format!("The line `{line}` doesn't start with a valid ID."))?;
 
-    Ok(Vec::from_iter(current.into_iter().zip(target.into_iter())))
+    Ok((id, path))
 }
 
 /// Filter changes and only keeps the ones with an Ok result.
-pub fn keep_valid_renamings(renamings: Vec<Validation>) -> Vec<Renaming> {
+pub fn keep_valid_changes(renamings: Vec<Validation>) -> Vec<Change> {
     renamings
         .into_iter()
         .filter_map(|validation| validation.ok())
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_the_id_from_the_path() {
+        assert_eq!(split_line_id("0042\tpath.txt"), Ok((42, "path.txt")));
+        assert_eq!(split_line_id(" 7 \t  spaced "), Ok((7, "  spaced ")));
+        assert_eq!(split_line_id("0001\ta\tb"), Ok((1, "a\tb")));
+    }
+
+    #[test]
+    fn reads_a_line_with_only_an_id_as_an_empty_path() {
+        assert_eq!(split_line_id("0042"), Ok((42, "")));
+        assert_eq!(split_line_id("0042\t"), Ok((42, "")));
+    }
+
+    #[test]
+    fn refuses_lines_without_a_valid_id() {
+        assert!(split_line_id("path.txt").is_err());
+        assert!(split_line_id("id\tpath.txt").is_err());
+        assert!(split_line_id("-1\tpath.txt").is_err());
+    }
+
+    #[test]
+    fn matches_the_lines_by_id() {
+        let format = BufferFormat {
+            layout: BufferLayout::TwoFiles,
+            line_ids: true,
+            allow_delete: true,
+        };
+
+        let current = ["0001\ta", "0002\tb", "0003\tc"].map(String::from).to_vec();
+        let target = ["0003\tz", "0001\t!delete old", ""]
+            .map(String::from)
+            .to_vec();
+
+        assert_eq!(
+            combine_paths_vecs(current, target, format),
+            Ok(vec![
+                (PathBuf::from("a"), PathBuf::new()),
+                (PathBuf::from("b"), PathBuf::new()),
+                (PathBuf::from("c"), PathBuf::from("z")),
+            ])
+        );
+    }
+
+    #[test]
+    fn refuses_duplicate_and_unknown_ids() {
+        let format = BufferFormat {
+            layout: BufferLayout::TwoFiles,
+            line_ids: true,
+            allow_delete: false,
+        };
+
+        let current = vec!["0001\ta".to_string()];
+
+        let duplicate = ["0001\tb", "0001\tc"].map(String::from).to_vec();
+        assert!(combine_paths_vecs(current.clone(), duplicate, format).is_err());
+
+        let unknown = vec!["0002\tb".to_string()];
+        assert!(combine_paths_vecs(current, unknown, format).is_err());
+    }
+}